@@ -1,4 +1,31 @@
+use std::{env, fs, io::Write, path::PathBuf};
+
+use flate2::{write::GzEncoder, Compression};
+
 fn main() {
+    // Each chip has its own memory layout, copy the right one to where the linker can find it.
+    let memory = if env::var_os("CARGO_FEATURE_RP2350").is_some() {
+        Some("memory-rp2350.x")
+    } else if env::var_os("CARGO_FEATURE_RP2040").is_some() {
+        Some("memory-rp2040.x")
+    } else {
+        None
+    };
+
+    let out = PathBuf::from(env::var_os("OUT_DIR").unwrap());
+    if let Some(memory) = memory {
+        fs::copy(memory, out.join("memory.x")).unwrap();
+        println!("cargo::rustc-link-search={}", out.display());
+        println!("cargo::rerun-if-changed={memory}");
+    }
+
+    // The web page is served compressed, browsers decompress it themselves.
+    let page = fs::read("src/app/index.html").unwrap();
+    let mut encoder = GzEncoder::new(Vec::new(), Compression::best());
+    encoder.write_all(&page).unwrap();
+    fs::write(out.join("index.html.gz"), encoder.finish().unwrap()).unwrap();
+    println!("cargo::rerun-if-changed=src/app/index.html");
+
     #[cfg(feature = "defmt")]
     println!("cargo::rustc-link-arg=-Tdefmt.x");
 }
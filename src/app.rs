@@ -0,0 +1,1217 @@
+use core::fmt::Write;
+
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, Either};
+use embassy_time::{Duration, Timer};
+use heapless::{String, Vec};
+use log::{info, warn};
+use mcutie::{
+    homeassistant::{
+        binary_sensor::BinarySensorState,
+        light::{Color, LightState, SupportedColorMode},
+        sensor::{Sensor, SensorClass, SensorStateClass},
+        AvailabilityState, AvailabilityTopics, Device, Entity, Origin,
+    },
+    McutieBuilder, McutieTask, MqttMessage, PublishBytes, Publishable, Topic,
+};
+use portable_atomic::Ordering;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    board::{Board, ConfigStore},
+    buffer::ByteBuffer,
+    config::{Config, OutputConfig, SegmentConfig, MAX_SEGMENT_NAME_LEN},
+    homeassistant::{remove_discovery, DiagnosticSensor, EnumSensor, LedLight, Number, Select},
+    leds::{
+        add_white, realtime_source, spawn_leds, update_custom_pixels, ColorOrder, Effect,
+        EffectTuning, GradientStop, Interpolation, LedCommand, LedProgram, NamedPalette, Palette,
+        PowerModel, RealtimeSource, RenderStats, Segment, SegmentSettings, StripSettings,
+        WhiteChannel, CURRENT_MA, CUSTOM_PALETTE, DEFAULT_PALETTE, EFFECT_COUNT, EFFECT_NAMES,
+        LED_CHANNELS, MAX_OUTPUTS, MAX_PIXELS, MAX_SEGMENTS, MAX_STOPS, PALETTE_OPTIONS,
+        PALETTE_OPTION_COUNT, RGB,
+    },
+    realtime::{
+        artnet::{artnet_task, MAX_UNIVERSE as MAX_ARTNET_UNIVERSE},
+        ddp::ddp_task,
+        e131::e131_task,
+        RealtimeSettings, DMX_CHANNELS,
+    },
+};
+
+mod web;
+mod wled;
+
+use web::{handle_network_settings, web_task, WebCommand};
+use wled::handle_wled_command;
+
+const DEVICE_AVAILABILITY_TOPIC: Topic<&'static str> = Topic::Device("status");
+pub(crate) const LED_STATE_TOPICS: [Topic<&'static str>; MAX_SEGMENTS] = [
+    Topic::Device("leds/state"),
+    Topic::Device("leds2/state"),
+    Topic::Device("leds3/state"),
+    Topic::Device("leds4/state"),
+    Topic::Device("leds5/state"),
+    Topic::Device("leds6/state"),
+    Topic::Device("leds7/state"),
+    Topic::Device("leds8/state"),
+];
+const LED_COMMAND_TOPICS: [Topic<&'static str>; MAX_SEGMENTS] = [
+    Topic::Device("leds/set"),
+    Topic::Device("leds2/set"),
+    Topic::Device("leds3/set"),
+    Topic::Device("leds4/set"),
+    Topic::Device("leds5/set"),
+    Topic::Device("leds6/set"),
+    Topic::Device("leds7/set"),
+    Topic::Device("leds8/set"),
+];
+const EFFECT_STATE_TOPICS: [Topic<&'static str>; MAX_SEGMENTS] = [
+    Topic::Device("leds/effect/state"),
+    Topic::Device("leds2/effect/state"),
+    Topic::Device("leds3/effect/state"),
+    Topic::Device("leds4/effect/state"),
+    Topic::Device("leds5/effect/state"),
+    Topic::Device("leds6/effect/state"),
+    Topic::Device("leds7/effect/state"),
+    Topic::Device("leds8/effect/state"),
+];
+const EFFECT_COMMAND_TOPICS: [Topic<&'static str>; MAX_SEGMENTS] = [
+    Topic::Device("leds/effect/set"),
+    Topic::Device("leds2/effect/set"),
+    Topic::Device("leds3/effect/set"),
+    Topic::Device("leds4/effect/set"),
+    Topic::Device("leds5/effect/set"),
+    Topic::Device("leds6/effect/set"),
+    Topic::Device("leds7/effect/set"),
+    Topic::Device("leds8/effect/set"),
+];
+const PIXEL_COMMAND_TOPICS: [Topic<&'static str>; MAX_SEGMENTS] = [
+    Topic::Device("leds/pixels/set"),
+    Topic::Device("leds2/pixels/set"),
+    Topic::Device("leds3/pixels/set"),
+    Topic::Device("leds4/pixels/set"),
+    Topic::Device("leds5/pixels/set"),
+    Topic::Device("leds6/pixels/set"),
+    Topic::Device("leds7/pixels/set"),
+    Topic::Device("leds8/pixels/set"),
+];
+const STRIP_STATE_TOPIC: Topic<&'static str> = Topic::Device("strip/state");
+const STRIP_COMMAND_TOPIC: Topic<&'static str> = Topic::Device("strip/set");
+const CURRENT_STATE_TOPIC: Topic<&'static str> = Topic::Device("current/state");
+const FPS_STATE_TOPIC: Topic<&'static str> = Topic::Device("render/fps");
+const RENDER_TIME_STATE_TOPIC: Topic<&'static str> = Topic::Device("render/time");
+const OVERRUNS_STATE_TOPIC: Topic<&'static str> = Topic::Device("render/overruns");
+const REALTIME_STATE_TOPIC: Topic<&'static str> = Topic::Device("realtime/state");
+
+const DEVICE: Device<'static> = Device::new();
+const ORIGIN: Origin<'static> = Origin::new();
+
+type LedEntity<'a> = Entity<'a, 1, LedLight<'a, 4, EFFECT_COUNT>>;
+
+const LED_OBJECT_IDS: [&str; MAX_SEGMENTS] = [
+    "leds", "leds_2", "leds_3", "leds_4", "leds_5", "leds_6", "leds_7", "leds_8",
+];
+/// The names of segments that haven't been given one.
+const LED_NAMES: [&str; MAX_SEGMENTS] = [
+    "Leds", "Leds 2", "Leds 3", "Leds 4", "Leds 5", "Leds 6", "Leds 7", "Leds 8",
+];
+
+fn segment_name(config: &Config, segment: usize) -> &str {
+    config.segment_name(segment).unwrap_or(LED_NAMES[segment])
+}
+
+/// Names one of a segment's entities after the segment.
+fn entity_name(segment_name: &str, suffix: &str) -> String<32> {
+    let mut name = String::new();
+    let _ = write!(name, "{segment_name} {suffix}");
+    name
+}
+
+/// The light for a segment, segments of RGBW strips advertise control of the white channel.
+fn led_entity(segment: usize, white: Option<WhiteChannel>, config: &Config) -> LedEntity<'_> {
+    let color_mode = match white {
+        Some(_) => SupportedColorMode::Rgbw,
+        None => SupportedColorMode::Rgb,
+    };
+
+    Entity {
+        device: DEVICE,
+        origin: ORIGIN,
+        object_id: LED_OBJECT_IDS[segment],
+        unique_id: Some(LED_OBJECT_IDS[segment]),
+        name: segment_name(config, segment),
+        availability: AvailabilityTopics::All([DEVICE_AVAILABILITY_TOPIC]),
+        state_topic: LED_STATE_TOPICS[segment],
+        component: LedLight {
+            command_topic: LED_COMMAND_TOPICS[segment],
+            supported_color_modes: [
+                color_mode,
+                SupportedColorMode::Hs,
+                SupportedColorMode::Xy,
+                SupportedColorMode::ColorTemp,
+            ],
+            effects: EFFECT_NAMES,
+            min_mireds: config.min_mireds,
+            max_mireds: config.max_mireds,
+        },
+    }
+}
+
+const SPEED_OBJECT_IDS: [&str; MAX_SEGMENTS] = [
+    "leds_speed",
+    "leds_2_speed",
+    "leds_3_speed",
+    "leds_4_speed",
+    "leds_5_speed",
+    "leds_6_speed",
+    "leds_7_speed",
+    "leds_8_speed",
+];
+const INTENSITY_OBJECT_IDS: [&str; MAX_SEGMENTS] = [
+    "leds_intensity",
+    "leds_2_intensity",
+    "leds_3_intensity",
+    "leds_4_intensity",
+    "leds_5_intensity",
+    "leds_6_intensity",
+    "leds_7_intensity",
+    "leds_8_intensity",
+];
+
+const PALETTE_OBJECT_IDS: [&str; MAX_SEGMENTS] = [
+    "leds_palette",
+    "leds_2_palette",
+    "leds_3_palette",
+    "leds_4_palette",
+    "leds_5_palette",
+    "leds_6_palette",
+    "leds_7_palette",
+    "leds_8_palette",
+];
+
+type NumberEntity<'a> = Entity<'a, 1, Number<'a>>;
+type PaletteEntity<'a> = Entity<'a, 1, Select<'a, PALETTE_OPTION_COUNT>>;
+
+/// The palette used by a segment's effects, shares the segment's effect topics.
+fn palette_entity(segment: usize, name: &str) -> PaletteEntity<'_> {
+    Entity {
+        device: DEVICE,
+        origin: ORIGIN,
+        object_id: PALETTE_OBJECT_IDS[segment],
+        unique_id: Some(PALETTE_OBJECT_IDS[segment]),
+        name,
+        availability: AvailabilityTopics::All([DEVICE_AVAILABILITY_TOPIC]),
+        state_topic: EFFECT_STATE_TOPICS[segment],
+        component: Select {
+            command_topic: EFFECT_COMMAND_TOPICS[segment],
+            command_template: "{\"palette\":\"{{ value }}\"}",
+            value_template: "{{ value_json.palette or 'Default' }}",
+            options: PALETTE_OPTIONS,
+        },
+    }
+}
+
+/// The effect parameters exposed as sliders, all share the segment's effect topics.
+#[derive(Clone, Copy)]
+enum TuningField {
+    Speed,
+    Intensity,
+}
+
+impl TuningField {
+    /// Appended to the segment's name to name the entity.
+    fn label(self) -> &'static str {
+        match self {
+            Self::Speed => "speed",
+            Self::Intensity => "intensity",
+        }
+    }
+
+    fn entity(self, segment: usize, name: &str) -> NumberEntity<'_> {
+        let (object_id, command_template, value_template) = match self {
+            Self::Speed => (
+                SPEED_OBJECT_IDS[segment],
+                "{\"speed\":{{ value }}}",
+                "{{ value_json.speed }}",
+            ),
+            Self::Intensity => (
+                INTENSITY_OBJECT_IDS[segment],
+                "{\"intensity\":{{ value }}}",
+                "{{ value_json.intensity }}",
+            ),
+        };
+
+        Entity {
+            device: DEVICE,
+            origin: ORIGIN,
+            object_id,
+            unique_id: Some(object_id),
+            name,
+            availability: AvailabilityTopics::All([DEVICE_AVAILABILITY_TOPIC]),
+            state_topic: EFFECT_STATE_TOPICS[segment],
+            component: Number {
+                command_topic: EFFECT_COMMAND_TOPICS[segment],
+                command_template,
+                value_template,
+                min: 0,
+                max: 255,
+            },
+        }
+    }
+}
+
+const CURRENT_ENTITY: Entity<'static, 1, Sensor<'static>> = Entity {
+    device: DEVICE,
+    origin: ORIGIN,
+    object_id: "current",
+    unique_id: Some("current"),
+    name: "Estimated current",
+    availability: AvailabilityTopics::All([DEVICE_AVAILABILITY_TOPIC]),
+    state_topic: CURRENT_STATE_TOPIC,
+    component: Sensor {
+        device_class: Some(SensorClass::Current),
+        state_class: Some(SensorStateClass::Measurement),
+        unit_of_measurement: Some("mA"),
+    },
+};
+
+/// Reported while no stream is being shown.
+const REALTIME_IDLE: &str = "None";
+
+const REALTIME_ENTITY: Entity<'static, 1, EnumSensor<'static, 4>> = Entity {
+    device: DEVICE,
+    origin: ORIGIN,
+    object_id: "realtime",
+    unique_id: Some("realtime"),
+    name: "Realtime stream",
+    availability: AvailabilityTopics::All([DEVICE_AVAILABILITY_TOPIC]),
+    state_topic: REALTIME_STATE_TOPIC,
+    component: EnumSensor {
+        options: [
+            REALTIME_IDLE,
+            RealtimeSource::ALL[0].name(),
+            RealtimeSource::ALL[1].name(),
+            RealtimeSource::ALL[2].name(),
+        ],
+    },
+};
+
+type DiagnosticEntity = Entity<'static, 1, DiagnosticSensor<'static>>;
+
+const fn diagnostic_entity(
+    object_id: &'static str,
+    name: &'static str,
+    state_topic: Topic<&'static str>,
+    unit_of_measurement: Option<&'static str>,
+) -> DiagnosticEntity {
+    Entity {
+        device: DEVICE,
+        origin: ORIGIN,
+        object_id,
+        unique_id: Some(object_id),
+        name,
+        availability: AvailabilityTopics::All([DEVICE_AVAILABILITY_TOPIC]),
+        state_topic,
+        component: DiagnosticSensor {
+            unit_of_measurement,
+        },
+    }
+}
+
+const RENDER_ENTITIES: [DiagnosticEntity; 3] = [
+    diagnostic_entity("render_fps", "Frame rate", FPS_STATE_TOPIC, Some("fps")),
+    diagnostic_entity(
+        "render_time",
+        "Render time",
+        RENDER_TIME_STATE_TOPIC,
+        Some("µs"),
+    ),
+    diagnostic_entity(
+        "render_overruns",
+        "Frame overruns",
+        OVERRUNS_STATE_TOPIC,
+        None,
+    ),
+];
+
+#[embassy_executor::task]
+async fn mqtt_task(
+    runner: McutieTask<
+        'static,
+        &'static str,
+        PublishBytes<'static, &'static str, AvailabilityState>,
+        { MAX_SEGMENTS * 3 + 1 },
+    >,
+) {
+    runner.run().await;
+}
+
+async fn publish_value(topic: Topic<&'static str>, value: u32) {
+    let mut buffer = ByteBuffer::<16>::new();
+    let _ = write!(buffer, "{value}");
+    let _ = topic.with_bytes(buffer.buffer()).publish().await;
+}
+
+/// Periodically publishes the estimated current of all segments when it changes.
+#[embassy_executor::task]
+async fn current_task() {
+    let mut last = None;
+
+    loop {
+        let current = CURRENT_MA
+            .iter()
+            .map(|current| current.load(Ordering::Relaxed))
+            .sum();
+        if last != Some(current) {
+            publish_value(CURRENT_STATE_TOPIC, current).await;
+            last = Some(current);
+        }
+
+        Timer::after_secs(5).await;
+    }
+}
+
+/// Periodically publishes how well the render loops are keeping up when it changes.
+#[embassy_executor::task]
+async fn render_stats_task() {
+    let mut last = None;
+
+    loop {
+        let stats = RenderStats::combined();
+        if last != Some(stats) {
+            publish_value(FPS_STATE_TOPIC, stats.fps).await;
+            publish_value(RENDER_TIME_STATE_TOPIC, stats.render_us).await;
+            publish_value(OVERRUNS_STATE_TOPIC, stats.overruns).await;
+            last = Some(stats);
+        }
+
+        Timer::after_secs(10).await;
+    }
+}
+
+/// Publishes which realtime protocol is overriding the programs whenever that changes.
+#[embassy_executor::task]
+async fn realtime_status_task() {
+    let mut last = None;
+
+    loop {
+        let source = realtime_source();
+        if last != Some(source) {
+            let state = source.map(|source| source.name()).unwrap_or(REALTIME_IDLE);
+            let _ = REALTIME_STATE_TOPIC
+                .with_bytes(state.as_bytes())
+                .publish()
+                .await;
+            last = Some(source);
+        }
+
+        Timer::after_secs(1).await;
+    }
+}
+
+/// The parts of a light command that `LightState` doesn't decode.
+#[derive(Deserialize, Default)]
+struct LightCommandExtras {
+    /// Sent alongside a colour, `LightState` only decodes it when sent alone.
+    brightness: Option<u8>,
+    /// In seconds.
+    transition: Option<f32>,
+}
+
+impl LightCommandExtras {
+    fn from_payload(payload: &[u8]) -> Self {
+        serde_json_core::from_slice(payload)
+            .map(|(extras, _)| extras)
+            .unwrap_or_default()
+    }
+
+    /// The requested transition, or the configured default.
+    fn transition(&self, config: &Config) -> Duration {
+        let millis = match self.transition {
+            Some(seconds) => seconds_to_millis(seconds),
+            None => config.transition_ms,
+        };
+
+        Duration::from_millis(millis.into())
+    }
+}
+
+/// A segment in a strip command.
+#[derive(Deserialize)]
+struct SegmentCommand<'a> {
+    name: Option<&'a str>,
+    /// Defaults to the first output.
+    output: Option<u8>,
+    /// Defaults to the start of the output.
+    start: Option<u16>,
+    pixels: u16,
+    reverse: Option<bool>,
+}
+
+/// A change to the strip settings, any missing fields are left unchanged.
+#[derive(Deserialize)]
+struct StripCommand<'a> {
+    /// The output that `pixels` and `order` apply to, defaults to the first.
+    output: Option<u8>,
+    pixels: Option<u16>,
+    order: Option<ColorOrder>,
+    /// Switches the output between RGB and RGBW strips.
+    rgbw: Option<bool>,
+    /// The colour temperature of the white LEDs in Kelvin, 0 for a neutral white.
+    white_temperature: Option<u16>,
+    /// The default transition in seconds.
+    transition: Option<f32>,
+    ma_per_channel: Option<u16>,
+    /// The power budget in milliamps, 0 disables limiting.
+    power_budget: Option<u16>,
+    /// The colour temperature range offered by Home Assistant, in mireds.
+    min_mireds: Option<u16>,
+    max_mireds: Option<u16>,
+    /// Replaces all of the segments, an empty list makes each output a single segment.
+    #[serde(borrow)]
+    segments: Option<Vec<SegmentCommand<'a>, MAX_SEGMENTS>>,
+    /// The E1.31 universe holding the first pixel, 0 ignores E1.31.
+    e131_universe: Option<u16>,
+    /// The channel of the first pixel in its E1.31 universe, from 1.
+    e131_start_channel: Option<u16>,
+    /// How long a realtime stream is shown after its last packet, in seconds.
+    realtime_timeout: Option<f32>,
+    artnet: Option<bool>,
+    /// The Art-Net port address holding the first pixel.
+    artnet_universe: Option<u16>,
+    /// The channel of the first pixel in its Art-Net universe, from 1.
+    artnet_start_channel: Option<u16>,
+    ddp: Option<bool>,
+}
+
+#[derive(Serialize)]
+struct OutputState {
+    pixels: u16,
+    order: ColorOrder,
+    rgbw: bool,
+    white_temperature: u16,
+}
+
+impl From<&OutputConfig> for OutputState {
+    fn from(output: &OutputConfig) -> Self {
+        Self {
+            pixels: output.pixels,
+            order: output.color_order,
+            rgbw: output.white.is_some(),
+            white_temperature: output.white.map(|white| white.kelvin()).unwrap_or_default(),
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct SegmentState<'a> {
+    name: &'a str,
+    output: u8,
+    start: u16,
+    pixels: u16,
+    reverse: bool,
+}
+
+impl<'a> From<&'a SegmentConfig> for SegmentState<'a> {
+    fn from(segment: &'a SegmentConfig) -> Self {
+        Self {
+            name: &segment.name,
+            output: segment.output,
+            start: segment.start,
+            pixels: segment.pixels,
+            reverse: segment.reverse,
+        }
+    }
+}
+
+#[derive(Serialize)]
+struct StripState<'a> {
+    outputs: [OutputState; MAX_OUTPUTS],
+    transition: f32,
+    ma_per_channel: u16,
+    power_budget: u16,
+    min_mireds: u16,
+    max_mireds: u16,
+    segments: Vec<SegmentState<'a>, MAX_SEGMENTS>,
+    e131_universe: u16,
+    e131_start_channel: u16,
+    realtime_timeout: f32,
+    artnet: bool,
+    artnet_universe: u16,
+    artnet_start_channel: u16,
+    ddp: bool,
+}
+
+/// The longest encodings of the parts of the strip state, segment names can double in length as
+/// quotes and backslashes are escaped.
+const OUTPUT_STATE_LEN: usize = 70;
+const SEGMENT_STATE_LEN: usize = 67 + 2 * MAX_SEGMENT_NAME_LEN;
+/// The remaining settings, with room for the longest floats.
+const STRIP_SETTINGS_LEN: usize = 140;
+/// The E1.31, Art-Net and DDP settings.
+const REALTIME_SETTINGS_LEN: usize = 164;
+
+/// Room for the strip state with every output and segment, along with the commas between them.
+const STRIP_STATE_LEN: usize = STRIP_SETTINGS_LEN
+    + REALTIME_SETTINGS_LEN
+    + MAX_OUTPUTS * (OUTPUT_STATE_LEN + 1)
+    + MAX_SEGMENTS * (SEGMENT_STATE_LEN + 1);
+
+fn seconds_to_millis(seconds: f32) -> u16 {
+    (seconds.max(0.0) * 1000.0).min(u16::MAX as f32) as u16
+}
+
+/// A share of the power budget in proportion to `pixels`. Never rounds down to 0 as that would
+/// disable limiting.
+fn budget_share(budget_ma: u16, pixels: u32, total_pixels: u32) -> u32 {
+    match (budget_ma, total_pixels) {
+        (0, _) | (_, 0) => 0,
+        (budget_ma, total) => (u32::from(budget_ma) * pixels / total).max(1),
+    }
+}
+
+/// Applies the configured layouts, the power budget is shared between the segments in proportion
+/// to their length. Realtime streams cover whole outputs so share it between the outputs instead.
+fn apply_strip_settings(config: &Config) {
+    let output_pixels: u32 = config
+        .outputs
+        .iter()
+        .map(|output| u32::from(output.pixels))
+        .sum();
+
+    for (index, output) in config.outputs.iter().enumerate() {
+        let budget_ma = budget_share(
+            config.power_budget_ma,
+            u32::from(output.pixels),
+            output_pixels,
+        );
+
+        StripSettings::set(
+            index,
+            StripSettings {
+                pixels: output.pixels as usize,
+                order: output.color_order,
+                white: output.white,
+                power: PowerModel {
+                    ma_per_channel: config.ma_per_channel,
+                    budget_ma,
+                    white: output.white,
+                },
+            },
+        );
+    }
+
+    let segments: [Segment; MAX_SEGMENTS] = core::array::from_fn(|index| config.segment(index));
+    let total_pixels: u32 = segments.iter().map(|segment| segment.pixels as u32).sum();
+
+    for (index, segment) in segments.into_iter().enumerate() {
+        let budget_ma = budget_share(config.power_budget_ma, segment.pixels as u32, total_pixels);
+
+        SegmentSettings::set(
+            index,
+            SegmentSettings {
+                power: PowerModel {
+                    ma_per_channel: config.ma_per_channel,
+                    budget_ma,
+                    white: config
+                        .outputs
+                        .get(segment.output)
+                        .and_then(|output| output.white),
+                },
+                segment,
+            },
+        );
+    }
+
+    RealtimeSettings::set(RealtimeSettings {
+        e131: config.e131(),
+        artnet: config.artnet(),
+        ddp: config.ddp_enabled,
+        timeout: Duration::from_millis(config.realtime_timeout_ms.into()),
+    });
+}
+
+async fn publish_strip_state(config: &Config) {
+    let mut buffer = ByteBuffer::<STRIP_STATE_LEN>::new();
+    if buffer
+        .serialize(&StripState {
+            outputs: [
+                (&config.outputs[0]).into(),
+                (&config.outputs[1]).into(),
+                (&config.outputs[2]).into(),
+                (&config.outputs[3]).into(),
+            ],
+            transition: config.transition_ms as f32 / 1000.0,
+            ma_per_channel: config.ma_per_channel,
+            power_budget: config.power_budget_ma,
+            min_mireds: config.min_mireds,
+            max_mireds: config.max_mireds,
+            segments: config.segments.iter().map(SegmentState::from).collect(),
+            e131_universe: config.e131_universe,
+            e131_start_channel: config.e131_start_channel,
+            realtime_timeout: config.realtime_timeout_ms as f32 / 1000.0,
+            artnet: config.artnet_enabled,
+            artnet_universe: config.artnet_universe,
+            artnet_start_channel: config.artnet_start_channel,
+            ddp: config.ddp_enabled,
+        })
+        .is_err()
+    {
+        warn!("Failed to encode strip state");
+        return;
+    }
+
+    let _ = STRIP_STATE_TOPIC
+        .with_bytes(buffer.buffer())
+        .publish()
+        .await;
+}
+
+/// Applies a strip layout command, returning true if the configuration changed.
+async fn handle_strip_command(payload: &[u8], config: &mut Config, store: ConfigStore) -> bool {
+    let command = match serde_json_core::from_slice::<StripCommand>(payload) {
+        Ok((command, _)) => command,
+        Err(_) => {
+            warn!("Failed to decode strip settings");
+            return false;
+        }
+    };
+
+    let index = command.output.unwrap_or(0) as usize;
+    if index >= MAX_OUTPUTS {
+        warn!("Unknown output {index}");
+        return false;
+    }
+
+    let mut new_config = config.clone();
+    let output = &mut new_config.outputs[index];
+    if let Some(pixels) = command.pixels {
+        output.pixels = pixels.min(MAX_PIXELS as u16);
+    }
+    if let Some(order) = command.order {
+        output.color_order = order;
+    }
+    match (command.rgbw, command.white_temperature) {
+        (Some(false), _) => output.white = None,
+        (Some(true), None) => {
+            output.white = output.white.or(Some(WhiteChannel::Neutral));
+        }
+        (_, Some(kelvin)) if command.rgbw.is_some() || output.white.is_some() => {
+            output.white = Some(WhiteChannel::from_kelvin(kelvin));
+        }
+        _ => {}
+    }
+    if let Some(transition) = command.transition {
+        new_config.transition_ms = seconds_to_millis(transition);
+    }
+    if let Some(ma_per_channel) = command.ma_per_channel {
+        new_config.ma_per_channel = ma_per_channel;
+    }
+    if let Some(power_budget) = command.power_budget {
+        new_config.power_budget_ma = power_budget;
+    }
+    if let Some(min_mireds) = command.min_mireds {
+        new_config.min_mireds = min_mireds.max(1);
+    }
+    if let Some(max_mireds) = command.max_mireds {
+        new_config.max_mireds = max_mireds;
+    }
+    if new_config.max_mireds < new_config.min_mireds {
+        warn!("Colour temperature range is empty");
+        return false;
+    }
+    if let Some(segments) = command.segments {
+        new_config.segments.clear();
+        for segment in segments {
+            let mut name = String::new();
+            if name.push_str(segment.name.unwrap_or_default()).is_err() {
+                warn!("Segment names are limited to {MAX_SEGMENT_NAME_LEN} bytes");
+                return false;
+            }
+
+            let segment = SegmentConfig {
+                name,
+                output: segment.output.unwrap_or(0),
+                start: segment.start.unwrap_or(0),
+                pixels: segment.pixels,
+                reverse: segment.reverse.unwrap_or(false),
+            };
+            if segment.output as usize >= MAX_OUTPUTS {
+                warn!("Unknown output {}", segment.output);
+                return false;
+            }
+            if usize::from(segment.start) + usize::from(segment.pixels) > MAX_PIXELS {
+                warn!("Segment {} is beyond the longest strip", segment.name);
+                return false;
+            }
+
+            let _ = new_config.segments.push(segment);
+        }
+    }
+
+    if let Some(universe) = command.e131_universe {
+        new_config.e131_universe = universe;
+    }
+    if let Some(timeout) = command.realtime_timeout {
+        new_config.realtime_timeout_ms = seconds_to_millis(timeout);
+    }
+    if let Some(artnet) = command.artnet {
+        new_config.artnet_enabled = artnet;
+    }
+    if let Some(ddp) = command.ddp {
+        new_config.ddp_enabled = ddp;
+    }
+    if let Some(universe) = command.artnet_universe {
+        if universe > MAX_ARTNET_UNIVERSE {
+            warn!("Art-Net universes are limited to {MAX_ARTNET_UNIVERSE}");
+            return false;
+        }
+        new_config.artnet_universe = universe;
+    }
+    for (channel, target) in [
+        (
+            command.e131_start_channel,
+            &mut new_config.e131_start_channel,
+        ),
+        (
+            command.artnet_start_channel,
+            &mut new_config.artnet_start_channel,
+        ),
+    ] {
+        if let Some(channel) = channel {
+            if !(1..=DMX_CHANNELS as u16).contains(&channel) {
+                warn!("Start channels must be between 1 and {DMX_CHANNELS}");
+                return false;
+            }
+            *target = channel;
+        }
+    }
+
+    if new_config == *config {
+        return false;
+    }
+
+    *config = new_config;
+    apply_strip_settings(config);
+
+    if let Err(e) = store.save(config).await {
+        warn!("Failed to save configuration: {e:?}");
+    } else {
+        info!("Saved strip settings");
+    }
+
+    true
+}
+
+/// A change to a segment's effect parameters, any missing fields are left unchanged.
+#[derive(Deserialize)]
+struct EffectCommand<'a> {
+    speed: Option<u8>,
+    intensity: Option<u8>,
+    /// The name of a built-in palette, or `DEFAULT_PALETTE`.
+    #[serde(borrow)]
+    palette: Option<&'a str>,
+    /// A custom palette as `[position, red, green, blue]` stops, replaces any named palette.
+    gradient: Option<Vec<[u8; 4], MAX_STOPS>>,
+    /// How to blend between the palette's stops.
+    blend: Option<Interpolation>,
+}
+
+async fn publish_effect_state(segment: usize) {
+    let mut buffer = ByteBuffer::<64>::new();
+    if buffer.serialize(&EffectTuning::get(segment)).is_err() {
+        warn!("Failed to encode effect state");
+        return;
+    }
+
+    let _ = EFFECT_STATE_TOPICS[segment]
+        .with_bytes(buffer.buffer())
+        .publish()
+        .await;
+}
+
+/// Applies new effect parameters, the running effect picks them up on its next frame.
+async fn handle_effect_command(segment: usize, payload: &[u8]) {
+    let command = match serde_json_core::from_slice::<EffectCommand>(payload) {
+        Ok((command, _)) => command,
+        Err(_) => {
+            warn!("Failed to decode effect settings");
+            return;
+        }
+    };
+
+    let mut tuning = EffectTuning::get(segment);
+    if let Some(speed) = command.speed {
+        tuning.speed = speed;
+    }
+    if let Some(intensity) = command.intensity {
+        tuning.intensity = intensity;
+    }
+    if let Some(gradient) = command.gradient {
+        let mut stops = [GradientStop::default(); MAX_STOPS];
+        for (stop, [position, r, g, b]) in stops.iter_mut().zip(&gradient) {
+            *stop = GradientStop {
+                position: *position,
+                color: RGB {
+                    r: *r,
+                    g: *g,
+                    b: *b,
+                },
+            };
+        }
+
+        match Palette::custom(&stops[..gradient.len()], command.blend.unwrap_or_default()) {
+            Some(palette) => tuning.palette = Some(palette),
+            None => warn!("Custom palettes need at least one stop"),
+        }
+    } else if let Some(name) = command.palette {
+        if name.eq_ignore_ascii_case(DEFAULT_PALETTE) {
+            tuning.palette = None;
+        } else if let Some(palette) = NamedPalette::from_name(name) {
+            tuning.palette = Some(palette.palette());
+        } else if name.eq_ignore_ascii_case(CUSTOM_PALETTE) {
+            // Custom palettes are only made from gradients, selecting one keeps the current
+            // palette.
+        } else {
+            warn!("Unknown palette {name}");
+        }
+    }
+    if let (Some(blend), Some(palette)) = (command.blend, &mut tuning.palette) {
+        palette.interpolation = blend;
+    }
+    EffectTuning::set(segment, tuning);
+
+    publish_effect_state(segment).await;
+}
+
+/// The program running on a segment and the last one that wasn't `Off`, used when turning back on.
+#[derive(Clone, Copy)]
+struct SegmentPrograms {
+    current: LedProgram,
+    last: LedProgram,
+}
+
+impl SegmentPrograms {
+    const fn new() -> Self {
+        Self {
+            current: LedProgram::Off,
+            last: LedProgram::Solid {
+                red: 255,
+                green: 255,
+                blue: 255,
+                brightness: 255,
+            },
+        }
+    }
+}
+
+/// Applies a light command to a segment.
+async fn handle_light_command(
+    segment: usize,
+    payload: &[u8],
+    programs: &mut SegmentPrograms,
+    config: &Config,
+) {
+    let light_state = match LightState::from_payload(payload) {
+        Ok(light_state) => light_state,
+        Err(_) => {
+            warn!("Failed to decode state");
+            return;
+        }
+    };
+    let extras = LightCommandExtras::from_payload(payload);
+
+    let new_program = if light_state.state == BinarySensorState::Off {
+        LedProgram::Off
+    } else {
+        // Commands apply on top of what is showing, or what was last shown when turning back on.
+        let base = match programs.current {
+            LedProgram::Off => programs.last,
+            program => program,
+        };
+
+        let program = if let Some(name) = light_state.effect {
+            match Effect::from_name(name) {
+                Some(effect) => LedProgram::Effect {
+                    effect,
+                    // Effects are drawn in the colour that was showing.
+                    color: base.color().unwrap_or(RGB {
+                        r: 255,
+                        g: 255,
+                        b: 255,
+                    }),
+                    brightness: base.brightness(),
+                },
+                None => {
+                    warn!("Unknown effect {name}");
+                    // Re-publish the current state so Home Assistant reverts the selection.
+                    programs.current.publish_state(segment).await;
+                    return;
+                }
+            }
+        } else {
+            match light_state.color {
+                Color::None => base,
+                Color::Brightness(brightness) => base.with_brightness(brightness),
+                Color::Rgb { red, green, blue } => LedProgram::Solid {
+                    red,
+                    green,
+                    blue,
+                    brightness: base.brightness(),
+                },
+                Color::Hs { hue, saturation } => LedProgram::Hs {
+                    hue,
+                    saturation,
+                    brightness: base.brightness(),
+                },
+                Color::Xy { x, y } => LedProgram::Xy {
+                    x,
+                    y,
+                    brightness: base.brightness(),
+                },
+                Color::ColorTemp(mireds) => LedProgram::ColorTemp {
+                    mireds: mireds.clamp(
+                        config.min_mireds.min(config.max_mireds),
+                        config.max_mireds.max(config.min_mireds),
+                    ),
+                    brightness: base.brightness(),
+                },
+                Color::Rgbw {
+                    red,
+                    green,
+                    blue,
+                    white,
+                } => {
+                    // Programs work in RGB, the white is extracted again as frames are output.
+                    let rgb = RGB {
+                        r: red,
+                        g: green,
+                        b: blue,
+                    };
+                    let output = config.segment(segment).output;
+                    let rgb = match config.outputs[output].white {
+                        Some(channel) => add_white(rgb, white, channel),
+                        None => rgb,
+                    };
+
+                    LedProgram::Solid {
+                        red: rgb.r,
+                        green: rgb.g,
+                        blue: rgb.b,
+                        brightness: base.brightness(),
+                    }
+                }
+                _ => {
+                    warn!("Unexpected color state received.");
+                    return;
+                }
+            }
+        };
+
+        match extras.brightness {
+            Some(brightness) => program.with_brightness(brightness),
+            None => program,
+        }
+    };
+
+    LED_CHANNELS[segment]
+        .send(LedCommand {
+            program: new_program,
+            transition: extras.transition(config),
+        })
+        .await;
+    programs.current = new_program;
+    if !matches!(new_program, LedProgram::Off) {
+        programs.last = new_program;
+    }
+}
+
+/// Updates a segment's custom pixels, switching it to the custom program if it is showing
+/// something else.
+async fn handle_pixels_command(
+    segment: usize,
+    payload: &[u8],
+    programs: &mut SegmentPrograms,
+    config: &Config,
+) {
+    if let Err(error) = update_custom_pixels(segment, payload) {
+        warn!("Failed to decode pixels: {error:?}");
+        return;
+    }
+
+    let (program, transition) = match programs.current {
+        // Updates to the pixels already showing are applied immediately.
+        program @ LedProgram::Custom { .. } => (program, 0),
+        LedProgram::Off => (
+            LedProgram::Custom {
+                brightness: programs.last.brightness(),
+            },
+            config.transition_ms,
+        ),
+        program => (
+            LedProgram::Custom {
+                brightness: program.brightness(),
+            },
+            config.transition_ms,
+        ),
+    };
+
+    LED_CHANNELS[segment]
+        .send(LedCommand {
+            program,
+            transition: Duration::from_millis(transition.into()),
+        })
+        .await;
+    programs.current = program;
+    programs.last = program;
+}
+
+/// Advertises a light and its effect parameters for each segment with pixels.
+/// Publishes the entities of the enabled segments and removes those of the rest, which may have
+/// been published before the segments changed.
+async fn publish_discovery(config: &Config, board_id: &str) {
+    for index in 0..MAX_SEGMENTS {
+        let segment = config.segment(index);
+        let name = segment_name(config, index);
+        let white = config.outputs[segment.output].white;
+
+        if !segment.is_enabled() {
+            let _ = remove_discovery(&led_entity(index, white, config), board_id).await;
+            for field in [TuningField::Speed, TuningField::Intensity] {
+                let _ = remove_discovery(&field.entity(index, name), board_id).await;
+            }
+            let _ = remove_discovery(&palette_entity(index, name), board_id).await;
+            continue;
+        }
+
+        let _ = led_entity(index, white, config).publish_discovery().await;
+
+        for field in [TuningField::Speed, TuningField::Intensity] {
+            let label = entity_name(name, field.label());
+            let _ = field.entity(index, &label).publish_discovery().await;
+        }
+        let label = entity_name(name, "palette");
+        let _ = palette_entity(index, &label).publish_discovery().await;
+
+        publish_effect_state(index).await;
+    }
+}
+
+pub async fn main(spawner: Spawner) {
+    let (board, ws2812) = Board::init(&spawner).await;
+
+    let mut subscriptions = [STRIP_COMMAND_TOPIC; MAX_SEGMENTS * 3 + 1];
+    subscriptions[..MAX_SEGMENTS].copy_from_slice(&LED_COMMAND_TOPICS);
+    subscriptions[MAX_SEGMENTS..MAX_SEGMENTS * 2].copy_from_slice(&EFFECT_COMMAND_TOPICS);
+    subscriptions[MAX_SEGMENTS * 2..MAX_SEGMENTS * 3].copy_from_slice(&PIXEL_COMMAND_TOPICS);
+
+    let (receiver, mqtt_runner) = McutieBuilder::new(board.network, "blinky", &board.config.broker)
+        .with_device_id(board.board_id)
+        .with_last_will(DEVICE_AVAILABILITY_TOPIC.with_bytes(AvailabilityState::Offline))
+        .with_subscriptions(subscriptions)
+        .build();
+
+    spawner.spawn(mqtt_task(mqtt_runner)).unwrap();
+
+    let mut config = board.config.clone();
+    apply_strip_settings(&config);
+    spawn_leds(&spawner, ws2812);
+    spawner.spawn(current_task()).unwrap();
+    spawner.spawn(render_stats_task()).unwrap();
+    spawner.spawn(e131_task(board.network)).unwrap();
+    spawner
+        .spawn(artnet_task(board.network, board.board_id))
+        .unwrap();
+    spawner.spawn(ddp_task(board.network)).unwrap();
+    spawner.spawn(realtime_status_task()).unwrap();
+    spawner
+        .spawn(web_task(board.network, board.board_id, board.config))
+        .unwrap();
+
+    let mut programs = [SegmentPrograms::new(); MAX_SEGMENTS];
+    for (channel, programs) in LED_CHANNELS.iter().zip(&programs) {
+        channel
+            .send(LedCommand {
+                program: programs.current,
+                transition: Duration::from_ticks(0),
+            })
+            .await;
+    }
+
+    loop {
+        wled::set_state(&programs, config.transition_ms);
+
+        let message = match select(receiver.receive(), web::receive_command()).await {
+            Either::First(message) => message,
+            Either::Second(WebCommand::State(command)) => {
+                let changed =
+                    handle_wled_command(command, &mut programs, &mut config, board.config_store)
+                        .await;
+                if changed {
+                    publish_strip_state(&config).await;
+                }
+                wled::set_state(&programs, config.transition_ms);
+                web::command_applied(true);
+                continue;
+            }
+            Either::Second(WebCommand::Network(settings)) => {
+                let saved =
+                    handle_network_settings(settings, &mut config, board.config_store).await;
+                web::command_applied(saved);
+                if saved {
+                    // Give the response time to be sent before restarting.
+                    Timer::after_secs(1).await;
+                    Board::reboot();
+                }
+                continue;
+            }
+        };
+
+        match message {
+            MqttMessage::Connected | MqttMessage::HomeAssistantOnline => {
+                board.led.set(true).await;
+
+                let _ = DEVICE_AVAILABILITY_TOPIC
+                    .with_bytes(AvailabilityState::Online)
+                    .publish()
+                    .await;
+
+                publish_discovery(&config, board.board_id).await;
+                let _ = CURRENT_ENTITY.publish_discovery().await;
+                for entity in &RENDER_ENTITIES {
+                    let _ = entity.publish_discovery().await;
+                }
+                let _ = REALTIME_ENTITY.publish_discovery().await;
+                publish_strip_state(&config).await;
+            }
+            MqttMessage::Disconnected => {
+                board.led.set(false).await;
+            }
+            MqttMessage::Publish(topic, buffer) => {
+                if topic == STRIP_COMMAND_TOPIC {
+                    if handle_strip_command(&buffer, &mut config, board.config_store).await {
+                        // Restart the current programs with the new layout.
+                        for (channel, programs) in LED_CHANNELS.iter().zip(&programs) {
+                            channel
+                                .send(LedCommand {
+                                    program: programs.current,
+                                    transition: Duration::from_ticks(0),
+                                })
+                                .await;
+                        }
+                        publish_discovery(&config, board.board_id).await;
+                    }
+                    publish_strip_state(&config).await;
+                } else if let Some(segment) = LED_COMMAND_TOPICS.iter().position(|t| topic == *t) {
+                    handle_light_command(segment, &buffer, &mut programs[segment], &config).await;
+                } else if let Some(segment) = EFFECT_COMMAND_TOPICS.iter().position(|t| topic == *t)
+                {
+                    handle_effect_command(segment, &buffer).await;
+                } else if let Some(segment) = PIXEL_COMMAND_TOPICS.iter().position(|t| topic == *t)
+                {
+                    handle_pixels_command(segment, &buffer, &mut programs[segment], &config).await;
+                }
+            }
+        }
+    }
+}
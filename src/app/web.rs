@@ -0,0 +1,187 @@
+//! The web server, serving a page to control and configure the device from a browser along with
+//! the API behind it. The page uses the WLED API to control the segments.
+
+use embassy_net::Stack;
+use embassy_sync::{
+    blocking_mutex::raw::CriticalSectionRawMutex, channel::Channel, signal::Signal,
+};
+use heapless::String;
+use log::{info, warn};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    app::wled::{StateCommand, WledApi},
+    board::ConfigStore,
+    buffer::ByteBuffer,
+    config::{Config, MAX_BROKER_LEN, MAX_PASSWORD_LEN, MAX_SSID_LEN},
+    http::{serve, Handler, Method, Request, Response, Status},
+    provisioning::is_provisioning,
+};
+
+/// The page, compressed at build time.
+static PAGE: &[u8] = include_bytes!(concat!(env!("OUT_DIR"), "/index.html.gz"));
+
+/// Changes made from the web that the main loop applies.
+pub(super) enum WebCommand {
+    State(StateCommand),
+    Network(NetworkSettings),
+}
+
+static WEB_COMMANDS: Channel<CriticalSectionRawMutex, WebCommand, 1> = Channel::new();
+
+/// Signalled with whether the main loop applied a change.
+static WEB_APPLIED: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Hands a change to the main loop and waits for it to be applied.
+pub(super) async fn send_command(command: WebCommand) -> bool {
+    WEB_APPLIED.reset();
+    WEB_COMMANDS.send(command).await;
+    WEB_APPLIED.wait().await
+}
+
+/// Waits for a change from the web.
+pub(super) async fn receive_command() -> WebCommand {
+    WEB_COMMANDS.receive().await
+}
+
+/// Tells the web server whether its change was applied.
+pub(super) fn command_applied(applied: bool) {
+    WEB_APPLIED.signal(applied);
+}
+
+/// The network settings, any missing fields are left unchanged. Changes take effect after a
+/// reboot.
+#[derive(Deserialize)]
+pub(super) struct NetworkSettings {
+    ssid: Option<String<MAX_SSID_LEN>>,
+    /// Empty for open networks.
+    password: Option<String<MAX_PASSWORD_LEN>>,
+    broker: Option<String<MAX_BROKER_LEN>>,
+    join_attempts: Option<u8>,
+}
+
+/// The network settings as shown on the page, the password is never sent back.
+#[derive(Serialize)]
+struct NetworkState<'a> {
+    id: &'a str,
+    ssid: &'a str,
+    broker: &'a str,
+    join_attempts: u8,
+    /// Whether the setup access point is running.
+    setup: bool,
+}
+
+/// Stores new network settings, returning whether they were valid and saved.
+pub(super) async fn handle_network_settings(
+    settings: NetworkSettings,
+    config: &mut Config,
+    store: ConfigStore,
+) -> bool {
+    let mut new_config = config.clone();
+    if let Some(ssid) = settings.ssid {
+        new_config.ssid = ssid;
+    }
+    if let Some(password) = settings.password {
+        new_config.password = password;
+    }
+    if let Some(broker) = settings.broker {
+        new_config.broker = broker;
+    }
+    if let Some(join_attempts) = settings.join_attempts {
+        new_config.join_attempts = join_attempts;
+    }
+
+    if new_config.ssid.is_empty() {
+        warn!("The network name can't be empty");
+        return false;
+    }
+    // WPA2 passphrases are 8 to 63 characters.
+    if !new_config.password.is_empty() && !(8..=63).contains(&new_config.password.len()) {
+        warn!("Network passwords must be 8 to 63 characters");
+        return false;
+    }
+
+    if let Err(e) = store.save(&new_config).await {
+        warn!("Failed to save network settings: {e:?}");
+        return false;
+    }
+
+    info!("Saved network settings");
+    *config = new_config;
+    true
+}
+
+struct WebServer {
+    wled: WledApi,
+    board_id: &'static str,
+    config: &'static Config,
+    buffer: ByteBuffer<384>,
+}
+
+impl WebServer {
+    fn network_state(&mut self) -> Response<'_> {
+        let state = NetworkState {
+            id: self.board_id,
+            ssid: &self.config.ssid,
+            broker: &self.config.broker,
+            join_attempts: self.config.join_attempts,
+            setup: is_provisioning(),
+        };
+
+        self.buffer = ByteBuffer::new();
+        if self.buffer.serialize(&state).is_err() {
+            warn!("Failed to encode network settings");
+            return Response::error(Status::InternalServerError);
+        }
+
+        Response::json(self.buffer.buffer())
+    }
+
+    async fn change_network(&mut self, body: &[u8]) -> Response<'_> {
+        let settings = match serde_json_core::from_slice::<NetworkSettings>(body) {
+            Ok((settings, _)) => settings,
+            Err(_) => return Response::error(Status::BadRequest),
+        };
+
+        if send_command(WebCommand::Network(settings)).await {
+            Response::json(b"{\"success\":true,\"reboot\":true}")
+        } else {
+            Response::error(Status::BadRequest)
+        }
+    }
+}
+
+impl Handler for WebServer {
+    async fn handle(&mut self, request: Request<'_>) -> Response<'_> {
+        match (request.method, request.path) {
+            (Method::Get, "/" | "/index.html") => Response::gzip("text/html", PAGE),
+            (Method::Get, "/api/settings") => self.network_state(),
+            (Method::Put | Method::Post, "/api/settings") => {
+                self.change_network(request.body).await
+            }
+            (_, "/" | "/index.html" | "/api/settings") => Response::error(Status::MethodNotAllowed),
+            // Devices joining the setup access point check for a captive portal at a variety of
+            // addresses, answering them with the page opens it.
+            (Method::Get, path) if is_provisioning() && !path.starts_with("/json") => {
+                Response::gzip("text/html", PAGE)
+            }
+            _ => self.wled.handle(request).await,
+        }
+    }
+}
+
+#[embassy_executor::task]
+pub(super) async fn web_task(
+    network: Stack<'static>,
+    board_id: &'static str,
+    config: &'static Config,
+) {
+    let mut server = WebServer {
+        wled: WledApi::new(network, board_id),
+        board_id,
+        config,
+        buffer: ByteBuffer::new(),
+    };
+
+    serve::<_, 2048>(network, 80, &mut server).await
+}
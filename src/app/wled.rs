@@ -0,0 +1,554 @@
+//! The core of WLED's JSON API, so WLED apps and dashboards can control the segments over HTTP.
+//! Each enabled segment appears as a WLED segment with the same index. Changes are handed to the
+//! main loop, which applies them alongside the commands from Home Assistant.
+
+use core::{cell::Cell, fmt::Write as _};
+
+use embassy_net::Stack;
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+use heapless::{String, Vec};
+use log::{info, warn};
+use portable_atomic::Ordering;
+use serde::{
+    de::{self, Unexpected, Visitor},
+    Deserialize, Deserializer, Serialize,
+};
+
+use crate::{
+    app::{
+        publish_effect_state,
+        web::{send_command, WebCommand},
+        SegmentPrograms,
+    },
+    board::ConfigStore,
+    buffer::ByteBuffer,
+    config::Config,
+    http::{Handler, Method, Request, Response, Status},
+    leds::{
+        is_realtime, realtime_source, Effect, EffectTuning, LedCommand, LedProgram, NamedPalette,
+        RenderStats, SegmentSettings, StripSettings, CURRENT_MA, EFFECT_COUNT, EFFECT_NAMES,
+        LED_CHANNELS, MAX_OUTPUTS, MAX_SEGMENTS, PALETTE_OPTIONS, PALETTE_OPTION_COUNT, RGB,
+    },
+};
+
+/// The WLED version reported to clients, some refuse to talk to older versions.
+const WLED_VERSION: &str = "0.14.0";
+
+#[cfg(feature = "rp2040")]
+const ARCH: &str = "rp2040";
+#[cfg(feature = "rp2350")]
+const ARCH: &str = "rp2350";
+
+/// WLED's first effect is a solid colour, the rest are the built-in effects.
+const WLED_EFFECTS: [&str; EFFECT_COUNT + 1] = {
+    let mut names = ["Solid"; EFFECT_COUNT + 1];
+    let mut i = 0;
+    while i < EFFECT_COUNT {
+        names[i + 1] = EFFECT_NAMES[i];
+        i += 1;
+    }
+    names
+};
+
+const WHITE: RGB = RGB {
+    r: 255,
+    g: 255,
+    b: 255,
+};
+
+/// The programs of each segment and the default transition as of the main loop's last change.
+static STATE: Mutex<CriticalSectionRawMutex, Cell<([SegmentPrograms; MAX_SEGMENTS], u16)>> =
+    Mutex::new(Cell::new(([SegmentPrograms::new(); MAX_SEGMENTS], 0)));
+
+/// Records the state reported to WLED clients.
+pub(super) fn set_state(programs: &[SegmentPrograms; MAX_SEGMENTS], transition_ms: u16) {
+    STATE.lock(|state| state.set((*programs, transition_ms)));
+}
+
+/// WLED's `on` field, which toggles when sent `"t"`.
+#[derive(Clone, Copy, PartialEq, Eq)]
+enum Switch {
+    On,
+    Off,
+    Toggle,
+}
+
+impl<'de> Deserialize<'de> for Switch {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct SwitchVisitor;
+
+        impl Visitor<'_> for SwitchVisitor {
+            type Value = Switch;
+
+            fn expecting(&self, formatter: &mut core::fmt::Formatter) -> core::fmt::Result {
+                formatter.write_str("a boolean or \"t\"")
+            }
+
+            fn visit_bool<E: de::Error>(self, value: bool) -> Result<Switch, E> {
+                Ok(if value { Switch::On } else { Switch::Off })
+            }
+
+            fn visit_str<E: de::Error>(self, value: &str) -> Result<Switch, E> {
+                match value {
+                    "t" => Ok(Switch::Toggle),
+                    _ => Err(E::invalid_value(Unexpected::Str(value), &self)),
+                }
+            }
+        }
+
+        deserializer.deserialize_any(SwitchVisitor)
+    }
+}
+
+/// A change to a segment, any missing fields are left unchanged.
+#[derive(Deserialize)]
+struct SegmentCommand {
+    /// Defaults to the segment's position in the list.
+    id: Option<u8>,
+    on: Option<Switch>,
+    bri: Option<u8>,
+    /// The primary, secondary and tertiary colours, only the primary is used.
+    col: Option<Vec<Vec<u8, 4>, 3>>,
+    fx: Option<u8>,
+    sx: Option<u8>,
+    ix: Option<u8>,
+    pal: Option<u8>,
+}
+
+/// A change to the state, the top level `on` and `bri` apply to every segment.
+#[derive(Deserialize)]
+pub(super) struct StateCommand {
+    on: Option<Switch>,
+    bri: Option<u8>,
+    /// The default transition, in tenths of a second. Saved, and used for this change unless `tt`
+    /// is given.
+    transition: Option<u16>,
+    /// The transition for this change alone, in tenths of a second.
+    tt: Option<u16>,
+    seg: Option<Vec<SegmentCommand, MAX_SEGMENTS>>,
+    /// Responds with the new state rather than just success.
+    v: Option<bool>,
+}
+
+/// The changes a command makes to one segment.
+#[derive(Clone, Copy, Default)]
+struct SegmentChange {
+    on: Option<Switch>,
+    brightness: Option<u8>,
+    color: Option<RGB>,
+    fx: Option<u8>,
+}
+
+impl SegmentChange {
+    fn is_empty(&self) -> bool {
+        self.on.is_none() && self.brightness.is_none() && self.color.is_none() && self.fx.is_none()
+    }
+
+    /// The segment's program after the change, `Off` unless the segment is left on.
+    fn apply(&self, programs: &SegmentPrograms) -> LedProgram {
+        let base = match programs.current {
+            LedProgram::Off => programs.last,
+            program => program,
+        };
+
+        let brightness = self
+            .brightness
+            .filter(|brightness| *brightness > 0)
+            .unwrap_or(base.brightness());
+        let color = self.color.or(base.color()).unwrap_or(WHITE);
+        let effect = match (self.fx, base) {
+            (Some(0), _) => None,
+            (Some(fx), _) => Effect::ALL.get(usize::from(fx) - 1).copied(),
+            (None, LedProgram::Effect { effect, .. }) => Some(effect),
+            (None, _) => None,
+        };
+
+        let program = match effect {
+            Some(effect) => LedProgram::Effect {
+                effect,
+                color,
+                brightness,
+            },
+            None if self.color.is_none() && self.fx.is_none() => base.with_brightness(brightness),
+            None => LedProgram::Solid {
+                red: color.r,
+                green: color.g,
+                blue: color.b,
+                brightness,
+            },
+        };
+
+        let on = match self.on {
+            Some(Switch::On) => true,
+            Some(Switch::Off) => false,
+            Some(Switch::Toggle) => matches!(programs.current, LedProgram::Off),
+            None => !matches!(programs.current, LedProgram::Off),
+        };
+
+        // WLED turns off when the brightness is set to 0.
+        if on && self.brightness != Some(0) {
+            program
+        } else {
+            LedProgram::Off
+        }
+    }
+}
+
+/// Applies a change from a WLED client to the segments, returning whether the configuration
+/// changed.
+pub(super) async fn handle_wled_command(
+    command: StateCommand,
+    programs: &mut [SegmentPrograms; MAX_SEGMENTS],
+    config: &mut Config,
+    store: ConfigStore,
+) -> bool {
+    let mut config_changed = false;
+    if let Some(tenths) = command.transition {
+        let transition_ms = tenths.saturating_mul(100);
+        if transition_ms != config.transition_ms {
+            config.transition_ms = transition_ms;
+            config_changed = true;
+
+            if let Err(e) = store.save(config).await {
+                warn!("Failed to save configuration: {e:?}");
+            } else {
+                info!("Saved the default transition");
+            }
+        }
+    }
+
+    let transition = match command.tt {
+        Some(tenths) => Duration::from_millis(u64::from(tenths) * 100),
+        None => Duration::from_millis(config.transition_ms.into()),
+    };
+
+    let mut changes = [SegmentChange::default(); MAX_SEGMENTS];
+    for (segment, change) in changes.iter_mut().enumerate() {
+        if config.segment(segment).is_enabled() {
+            change.on = command.on;
+            change.brightness = command.bri;
+        }
+    }
+
+    for (position, segment) in command.seg.unwrap_or_default().into_iter().enumerate() {
+        let index = segment.id.map_or(position, usize::from);
+        if index >= MAX_SEGMENTS || !config.segment(index).is_enabled() {
+            continue;
+        }
+
+        let change = &mut changes[index];
+        change.on = segment.on.or(change.on);
+        change.brightness = segment.bri.or(change.brightness);
+        if let Some([r, g, b, ..]) = segment
+            .col
+            .as_ref()
+            .and_then(|col| col.first())
+            .map(|c| c.as_slice())
+        {
+            change.color = Some(RGB {
+                r: *r,
+                g: *g,
+                b: *b,
+            });
+        }
+        change.fx = segment.fx.filter(|fx| usize::from(*fx) <= EFFECT_COUNT);
+
+        if segment.sx.is_some() || segment.ix.is_some() || segment.pal.is_some() {
+            let mut tuning = EffectTuning::get(index);
+            if let Some(speed) = segment.sx {
+                tuning.speed = speed;
+            }
+            if let Some(intensity) = segment.ix {
+                tuning.intensity = intensity;
+            }
+            match segment.pal.map(usize::from) {
+                Some(0) => tuning.palette = None,
+                Some(pal) => {
+                    if let Some(palette) = NamedPalette::ALL.get(pal - 1) {
+                        tuning.palette = Some(palette.palette());
+                    }
+                }
+                None => {}
+            }
+            EffectTuning::set(index, tuning);
+            publish_effect_state(index).await;
+        }
+    }
+
+    for (segment, (change, programs)) in changes.iter().zip(programs.iter_mut()).enumerate() {
+        if change.is_empty() {
+            continue;
+        }
+
+        let program = change.apply(programs);
+        if !matches!(program, LedProgram::Off) {
+            programs.last = program;
+        }
+        if program == programs.current {
+            continue;
+        }
+
+        LED_CHANNELS[segment]
+            .send(LedCommand {
+                program,
+                transition,
+            })
+            .await;
+        programs.current = program;
+    }
+
+    config_changed
+}
+
+#[derive(Serialize)]
+struct SegmentState {
+    id: u8,
+    start: u16,
+    stop: u16,
+    len: u16,
+    on: bool,
+    bri: u8,
+    col: [[u8; 3]; 3],
+    fx: u8,
+    sx: u8,
+    ix: u8,
+    pal: u8,
+    sel: bool,
+    rev: bool,
+}
+
+#[derive(Serialize)]
+struct State {
+    on: bool,
+    bri: u8,
+    transition: u16,
+    /// No presets or playlists.
+    ps: i8,
+    pl: i8,
+    mainseg: u8,
+    seg: Vec<SegmentState, MAX_SEGMENTS>,
+}
+
+fn state() -> State {
+    let (programs, transition_ms) = STATE.lock(|state| state.get());
+
+    let mut seg = Vec::new();
+    for (index, programs) in programs.iter().enumerate() {
+        let layout = SegmentSettings::get(index).segment;
+        if !layout.is_enabled() {
+            continue;
+        }
+
+        // WLED numbers the pixels of all the outputs together.
+        let offset: usize = (0..layout.output)
+            .map(|output| StripSettings::get(output).pixels)
+            .sum();
+        let start = offset + layout.start;
+
+        let shown = match programs.current {
+            LedProgram::Off => programs.last,
+            program => program,
+        };
+        let color = shown.color().unwrap_or_default();
+        let fx = match shown {
+            LedProgram::Effect { effect, .. } => Effect::ALL
+                .iter()
+                .position(|candidate| *candidate == effect)
+                .map_or(0, |position| position + 1),
+            _ => 0,
+        };
+        let tuning = EffectTuning::get(index);
+        let pal = tuning
+            .palette
+            .and_then(|palette| {
+                PALETTE_OPTIONS
+                    .iter()
+                    .position(|name| *name == palette.name())
+            })
+            .unwrap_or(0);
+
+        let _ = seg.push(SegmentState {
+            id: index as u8,
+            start: start as u16,
+            stop: (start + layout.pixels) as u16,
+            len: layout.pixels as u16,
+            on: !matches!(programs.current, LedProgram::Off),
+            bri: shown.brightness(),
+            col: [[color.r, color.g, color.b], [0; 3], [0; 3]],
+            fx: fx as u8,
+            sx: tuning.speed,
+            ix: tuning.intensity,
+            pal: pal as u8,
+            sel: true,
+            rev: layout.reverse,
+        });
+    }
+
+    State {
+        on: seg.iter().any(|segment| segment.on),
+        bri: seg
+            .iter()
+            .filter(|segment| segment.on)
+            .map(|segment| segment.bri)
+            .max()
+            .unwrap_or(0),
+        transition: transition_ms / 100,
+        ps: -1,
+        pl: -1,
+        mainseg: seg.first().map_or(0, |segment| segment.id),
+        seg,
+    }
+}
+
+#[derive(Serialize)]
+struct LedsInfo {
+    count: u16,
+    rgbw: bool,
+    fps: u32,
+    /// The estimated current, in milliamps.
+    pwr: u32,
+    /// The power budget, in milliamps.
+    maxpwr: u32,
+    maxseg: u8,
+}
+
+#[derive(Serialize)]
+struct Info<'a> {
+    ver: &'a str,
+    leds: LedsInfo,
+    name: &'a str,
+    live: bool,
+    /// The realtime protocol being shown.
+    lm: &'a str,
+    fxcount: u8,
+    palcount: u8,
+    arch: &'a str,
+    brand: &'a str,
+    product: &'a str,
+    mac: &'a str,
+    ip: &'a str,
+    uptime: u64,
+}
+
+fn ip_address(network: Stack<'static>) -> String<16> {
+    let mut ip = String::new();
+    if let Some(config) = network.config_v4() {
+        let _ = write!(ip, "{}", config.address.address());
+    }
+    ip
+}
+
+fn info<'a>(board_id: &'a str, ip: &'a str) -> Info<'a> {
+    let outputs = (0..MAX_OUTPUTS).map(StripSettings::get);
+
+    Info {
+        ver: WLED_VERSION,
+        leds: LedsInfo {
+            count: outputs.clone().map(|output| output.pixels as u16).sum(),
+            rgbw: outputs.clone().any(|output| output.white.is_some()),
+            fps: (0..MAX_SEGMENTS)
+                .map(|segment| RenderStats::get(segment).fps)
+                .max()
+                .unwrap_or(0),
+            pwr: CURRENT_MA
+                .iter()
+                .map(|current| current.load(Ordering::Relaxed))
+                .sum(),
+            maxpwr: outputs.map(|output| output.power.budget_ma).sum(),
+            maxseg: MAX_SEGMENTS as u8,
+        },
+        name: "blinky",
+        live: is_realtime(),
+        lm: realtime_source().map_or("", |source| source.name()),
+        fxcount: WLED_EFFECTS.len() as u8,
+        palcount: PALETTE_OPTION_COUNT as u8,
+        arch: ARCH,
+        brand: "blinky",
+        product: "blinky",
+        mac: board_id,
+        ip,
+        uptime: Instant::now().as_secs(),
+    }
+}
+
+/// Everything at once, as WLED clients fetch when they first connect.
+#[derive(Serialize)]
+struct Everything<'a> {
+    state: State,
+    info: Info<'a>,
+    effects: &'a [&'a str],
+    palettes: &'a [&'a str],
+}
+
+pub(super) struct WledApi {
+    network: Stack<'static>,
+    board_id: &'static str,
+    buffer: ByteBuffer<4096>,
+}
+
+impl WledApi {
+    pub(super) fn new(network: Stack<'static>, board_id: &'static str) -> Self {
+        Self {
+            network,
+            board_id,
+            buffer: ByteBuffer::new(),
+        }
+    }
+
+    fn respond(&mut self, value: &impl Serialize) -> Response<'_> {
+        self.buffer = ByteBuffer::new();
+        if self.buffer.serialize(value).is_err() {
+            warn!("Failed to encode WLED response");
+            return Response::error(Status::InternalServerError);
+        }
+
+        Response::json(self.buffer.buffer())
+    }
+
+    /// Hands a change to the main loop and waits for it to be applied.
+    async fn change_state(&mut self, body: &[u8]) -> Response<'_> {
+        let command = match serde_json_core::from_slice::<StateCommand>(body) {
+            Ok((command, _)) => command,
+            Err(_) => return Response::error(Status::BadRequest),
+        };
+        let verbose = command.v.unwrap_or(false);
+
+        send_command(WebCommand::State(command)).await;
+
+        if verbose {
+            self.respond(&state())
+        } else {
+            Response::json(b"{\"success\":true}")
+        }
+    }
+}
+
+impl Handler for WledApi {
+    async fn handle(&mut self, request: Request<'_>) -> Response<'_> {
+        match (request.method, request.path) {
+            (Method::Get, "/json") => {
+                let ip = ip_address(self.network);
+                let everything = Everything {
+                    state: state(),
+                    info: info(self.board_id, &ip),
+                    effects: &WLED_EFFECTS,
+                    palettes: &PALETTE_OPTIONS,
+                };
+                self.respond(&everything)
+            }
+            (Method::Get, "/json/state") => self.respond(&state()),
+            (Method::Get, "/json/info") => {
+                let ip = ip_address(self.network);
+                let info = info(self.board_id, &ip);
+                self.respond(&info)
+            }
+            (Method::Get, "/json/effects") => self.respond(&WLED_EFFECTS),
+            (Method::Post, "/json" | "/json/state") => self.change_state(request.body).await,
+            (_, "/json" | "/json/state" | "/json/info" | "/json/effects") => {
+                Response::error(Status::MethodNotAllowed)
+            }
+            _ => Response::error(Status::NotFound),
+        }
+    }
+}
@@ -0,0 +1,248 @@
+//! Runs an `LedProgram` against a virtual strip on the host.
+//!
+//! ```sh
+//! cargo run --no-default-features --features simulator --bin simulator -- [OPTIONS] PROGRAM
+//! ```
+//!
+//! `PROGRAM` is `off`, a solid colour given as `rrggbb` hex, a colour temperature such as `2700K`
+//! or the name of an effect. By default frames are rendered to the terminal using ANSI truecolor
+//! escapes.
+//!
+//! Options:
+//!
+//! * `--pixels <n>` - the length of the virtual strip, defaults to 50.
+//! * `--brightness <n>` - the brightness from 0 to 255, defaults to 255.
+//! * `--color <rrggbb>` - the colour used by effects, defaults to white.
+//! * `--speed <n>` - the speed of effects from 0 to 255, defaults to 128.
+//! * `--intensity <n>` - the effect specific intensity from 0 to 255, defaults to 128.
+//! * `--palette <name>` - the palette used by effects, defaults to each effect's own colours.
+//! * `--seconds <n>` - how long to run animated programs for, defaults to 10.
+//! * `--output <file>` - dump frames to a file instead, one line per frame.
+
+use std::{
+    env,
+    fs::File,
+    io::{self, BufWriter, Write},
+    process::ExitCode,
+    time::Instant,
+};
+
+use blinky_rs::leds::{
+    Effect, EffectTuning, FrameSink, LedProgram, NamedPalette, Output, MAX_PIXELS, RGB,
+};
+use embassy_futures::{block_on, select::select};
+use embassy_time::{Duration, Timer};
+
+/// Draws each frame over the previous one on a single terminal line.
+struct TerminalSink {
+    out: io::Stdout,
+}
+
+impl FrameSink for TerminalSink {
+    async fn write(&mut self, pixels: &[RGB]) {
+        let mut out = self.out.lock();
+
+        let _ = write!(out, "\r");
+        for pixel in pixels {
+            let _ = write!(out, "\x1b[48;2;{};{};{}m  ", pixel.r, pixel.g, pixel.b);
+        }
+        let _ = write!(out, "\x1b[0m");
+        let _ = out.flush();
+    }
+}
+
+/// Writes each frame as a line of the elapsed milliseconds followed by the pixels in hex.
+struct FileSink {
+    out: BufWriter<File>,
+    start: Instant,
+}
+
+impl FrameSink for FileSink {
+    async fn write(&mut self, pixels: &[RGB]) {
+        let _ = write!(self.out, "{}", self.start.elapsed().as_millis());
+        for pixel in pixels {
+            let _ = write!(self.out, " {:02x}{:02x}{:02x}", pixel.r, pixel.g, pixel.b);
+        }
+        let _ = writeln!(self.out);
+    }
+}
+
+fn parse_program(arg: &str) -> Option<LedProgram> {
+    if arg.eq_ignore_ascii_case("off") {
+        return Some(LedProgram::Off);
+    }
+
+    if let Some(effect) = Effect::from_name(arg) {
+        return Some(LedProgram::Effect {
+            effect,
+            color: RGB {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+            brightness: 255,
+        });
+    }
+
+    if let Some(kelvin) = arg.strip_suffix(['K', 'k']) {
+        let kelvin: u32 = kelvin.parse().ok()?;
+        return Some(LedProgram::ColorTemp {
+            mireds: (1_000_000 / kelvin.max(1)).clamp(1, u16::MAX.into()) as u16,
+            brightness: 255,
+        });
+    }
+
+    let color = parse_color(arg)?;
+    Some(LedProgram::Solid {
+        red: color.r,
+        green: color.g,
+        blue: color.b,
+        brightness: 255,
+    })
+}
+
+fn parse_color(arg: &str) -> Option<RGB> {
+    let hex = arg.strip_prefix('#').unwrap_or(arg);
+    if hex.len() != 6 {
+        return None;
+    }
+
+    let color = u32::from_str_radix(hex, 16).ok()?;
+    Some(RGB {
+        r: (color >> 16) as u8,
+        g: (color >> 8) as u8,
+        b: color as u8,
+    })
+}
+
+async fn simulate<S: FrameSink>(
+    program: LedProgram,
+    sink: &mut S,
+    pixels: usize,
+    duration: Duration,
+) {
+    let mut output = Output::new(sink, 0);
+    output.start(Duration::from_ticks(0), program.brightness());
+    let mut frame = [RGB::default(); MAX_PIXELS];
+    let frame = &mut frame[..pixels.min(MAX_PIXELS)];
+
+    // Animations only return when a new program arrives so stop them after the duration instead.
+    select(program.run(&mut output, frame, 0), Timer::after(duration)).await;
+}
+
+fn usage() -> ExitCode {
+    let names: Vec<&str> = Effect::ALL.iter().map(Effect::name).collect();
+    let palettes: Vec<&str> = NamedPalette::ALL.iter().map(NamedPalette::name).collect();
+    eprintln!(
+        "Usage: simulator [--pixels <n>] [--brightness <n>] [--color <rrggbb>] [--speed <n>] \
+         [--intensity <n>] [--palette <name>] [--seconds <n>] [--output <file>] \
+         <off|rrggbb|kelvinK|effect>"
+    );
+    eprintln!("Effects: {}", names.join(", "));
+    eprintln!("Palettes: {}", palettes.join(", "));
+    ExitCode::FAILURE
+}
+
+fn main() -> ExitCode {
+    let mut pixels = 50;
+    let mut brightness = 255;
+    let mut color = None;
+    let mut tuning = EffectTuning::DEFAULT;
+    let mut seconds = 10;
+    let mut output = None;
+    let mut program = None;
+
+    let mut args = env::args().skip(1);
+    while let Some(arg) = args.next() {
+        match arg.as_str() {
+            "--pixels" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(p) if p <= MAX_PIXELS => pixels = p,
+                _ => return usage(),
+            },
+            "--brightness" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(b) => brightness = b,
+                None => return usage(),
+            },
+            "--color" => match args.next().as_deref().and_then(parse_color) {
+                Some(c) => color = Some(c),
+                None => return usage(),
+            },
+            "--speed" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(s) => tuning.speed = s,
+                None => return usage(),
+            },
+            "--intensity" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(i) => tuning.intensity = i,
+                None => return usage(),
+            },
+            "--palette" => match args.next().as_deref().and_then(NamedPalette::from_name) {
+                Some(p) => tuning.palette = Some(p.palette()),
+                None => return usage(),
+            },
+            "--seconds" => match args.next().and_then(|s| s.parse().ok()) {
+                Some(s) => seconds = s,
+                None => return usage(),
+            },
+            "--output" => match args.next() {
+                Some(path) => output = Some(path),
+                None => return usage(),
+            },
+            arg => match parse_program(arg) {
+                Some(p) => program = Some(p),
+                None => {
+                    eprintln!("Unknown program {arg}");
+                    return usage();
+                }
+            },
+        }
+    }
+
+    let Some(program) = program else {
+        return usage();
+    };
+    let program = match program {
+        LedProgram::Effect {
+            effect,
+            color: default,
+            brightness,
+        } => LedProgram::Effect {
+            effect,
+            color: color.unwrap_or(default),
+            brightness,
+        },
+        program => program,
+    }
+    .with_brightness(brightness);
+    EffectTuning::set(0, tuning);
+    let duration = Duration::from_secs(seconds);
+
+    match output {
+        Some(path) => {
+            let file = match File::create(&path) {
+                Ok(file) => file,
+                Err(e) => {
+                    eprintln!("Failed to create {path}: {e}");
+                    return ExitCode::FAILURE;
+                }
+            };
+
+            let mut sink = FileSink {
+                out: BufWriter::new(file),
+                start: Instant::now(),
+            };
+            block_on(simulate(program, &mut sink, pixels, duration));
+
+            if let Err(e) = sink.out.flush() {
+                eprintln!("Failed to write {path}: {e}");
+                return ExitCode::FAILURE;
+            }
+        }
+        None => {
+            let mut sink = TerminalSink { out: io::stdout() };
+            block_on(simulate(program, &mut sink, pixels, duration));
+            println!();
+        }
+    }
+
+    ExitCode::SUCCESS
+}
@@ -0,0 +1,88 @@
+//! Board bring-up shared by the Pico W and Pico 2 W, which only differ in their chip and flash.
+
+use embassy_executor::Spawner;
+use embassy_net::Stack;
+use embassy_rp::gpio::{Input, Pull};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use static_cell::StaticCell;
+
+use super::{
+    storage::BoardFlash,
+    wifi::{self, Led, WifiPeripherals},
+    ws2812::{Ws2812Outputs, Ws2812Pins},
+    ConfigStore, FLASH_SIZE,
+};
+use crate::config::Config;
+
+#[derive(Clone, Copy)]
+pub struct Board {
+    pub board_id: &'static str,
+    pub network: Stack<'static>,
+    pub led: Led,
+    pub config: &'static Config,
+    pub config_store: ConfigStore,
+}
+
+/// Brings up the peripherals, flash, Wi-Fi and LED outputs. `board_id` reads the chip's unique
+/// id, given the flash in case that is where it is stored.
+pub async fn init(
+    spawner: &Spawner,
+    board_id: impl FnOnce(&mut BoardFlash<FLASH_SIZE>) -> &'static str,
+) -> (Board, Ws2812Outputs) {
+    let peripherals = embassy_rp::init(Default::default());
+
+    #[cfg(feature = "log")]
+    crate::usb::spawn_usb(spawner, peripherals.USB);
+
+    let mut flash = BoardFlash::<FLASH_SIZE>::new(peripherals.FLASH, peripherals.DMA_CH2);
+    let board_id = board_id(&mut flash);
+
+    static FLASH: StaticCell<Mutex<CriticalSectionRawMutex, BoardFlash<FLASH_SIZE>>> =
+        StaticCell::new();
+    let config_store = ConfigStore::new(FLASH.init(Mutex::new(flash)));
+    let config = config_store.load_or_default().await;
+
+    let network = wifi::init(
+        spawner,
+        WifiPeripherals {
+            pwr: peripherals.PIN_23,
+            dio: peripherals.PIN_24,
+            cs: peripherals.PIN_25,
+            clk: peripherals.PIN_29,
+            pio: peripherals.PIO0,
+            dma: peripherals.DMA_CH0,
+        },
+        // Held to start the setup access point.
+        Input::new(peripherals.PIN_22, Pull::Up),
+        config,
+        board_id,
+    )
+    .await;
+
+    let ws2812 = Ws2812Outputs::new(
+        peripherals.PIO1,
+        [
+            peripherals.DMA_CH1.into(),
+            peripherals.DMA_CH3.into(),
+            peripherals.DMA_CH4.into(),
+            peripherals.DMA_CH5.into(),
+        ],
+        Ws2812Pins {
+            pin0: peripherals.PIN_15,
+            pin1: peripherals.PIN_14,
+            pin2: peripherals.PIN_13,
+            pin3: peripherals.PIN_12,
+        },
+    );
+
+    (
+        Board {
+            board_id,
+            network,
+            led: Led,
+            config,
+            config_store,
+        },
+        ws2812,
+    )
+}
@@ -0,0 +1,24 @@
+//! Stand-ins for the board support needed to run the LED engine on the host.
+
+use rand::{thread_rng, RngCore};
+
+/// Random number source matching the on-chip `RoscRng` used on real boards.
+pub struct Rng;
+
+impl RngCore for Rng {
+    fn next_u32(&mut self) -> u32 {
+        thread_rng().next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        thread_rng().next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        thread_rng().fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), rand::Error> {
+        thread_rng().try_fill_bytes(dest)
+    }
+}
@@ -1,20 +1,32 @@
 use core::str;
 
+use embassy_executor::Spawner;
 use embassy_rp::block::ImageDef;
 use embassy_rp::{otp::get_chipid, rom_data::reboot};
 use static_cell::StaticCell;
 
+mod common;
+mod storage;
+mod wifi;
+mod ws2812;
+
+pub use common::Board;
+pub use embassy_rp::clocks::RoscRng as Rng;
+pub use wifi::Led;
+pub use ws2812::{Ws2812, Ws2812Outputs};
+
 #[link_section = ".start_block"]
 #[used]
 pub static IMAGE_DEF: ImageDef = ImageDef::secure_exe();
 
-static BOARD_ID: StaticCell<[u8; 16]> = StaticCell::new();
+/// The Pico 2 W has 4MB of flash.
+const FLASH_SIZE: usize = 4 * 1024 * 1024;
 
-pub fn reboot_to_bootsel() {
-    reboot(2, 0, 0, 0);
-}
+pub type ConfigStore = storage::ConfigStore<FLASH_SIZE>;
+
+static BOARD_ID: StaticCell<[u8; 16]> = StaticCell::new();
 
-pub fn board_id() -> &'static str {
+fn board_id() -> &'static str {
     let board_id = BOARD_ID.init_with(|| {
         let chip_id = match get_chipid() {
             Ok(u) => u.to_ne_bytes(),
@@ -28,3 +40,18 @@ pub fn board_id() -> &'static str {
 
     str::from_utf8(board_id).unwrap()
 }
+
+impl Board {
+    pub async fn init(spawner: &Spawner) -> (Self, Ws2812Outputs) {
+        common::init(spawner, |_| board_id()).await
+    }
+
+    pub fn reboot_to_bootsel() {
+        reboot(2, 0, 0, 0);
+    }
+
+    /// Restarts the firmware, picking up any newly stored configuration.
+    pub fn reboot() -> ! {
+        cortex_m::peripheral::SCB::sys_reset()
+    }
+}
@@ -0,0 +1,80 @@
+//! Stores the device configuration in the last sector of flash.
+
+use embassy_rp::{
+    flash::{Async, Flash, ERASE_SIZE},
+    peripherals::FLASH,
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, mutex::Mutex};
+use log::{info, warn};
+use static_cell::StaticCell;
+
+use crate::config::{Config, ConfigError, RECORD_SIZE};
+
+pub type BoardFlash<const FLASH_SIZE: usize> = Flash<'static, FLASH, Async, FLASH_SIZE>;
+
+/// Reads and writes the configuration record. The sector it uses must be reserved in the board's
+/// memory layout.
+pub struct ConfigStore<const FLASH_SIZE: usize> {
+    flash: &'static Mutex<CriticalSectionRawMutex, BoardFlash<FLASH_SIZE>>,
+}
+
+impl<const FLASH_SIZE: usize> Clone for ConfigStore<FLASH_SIZE> {
+    fn clone(&self) -> Self {
+        *self
+    }
+}
+
+impl<const FLASH_SIZE: usize> Copy for ConfigStore<FLASH_SIZE> {}
+
+impl<const FLASH_SIZE: usize> ConfigStore<FLASH_SIZE> {
+    const OFFSET: u32 = (FLASH_SIZE - ERASE_SIZE) as u32;
+
+    pub fn new(flash: &'static Mutex<CriticalSectionRawMutex, BoardFlash<FLASH_SIZE>>) -> Self {
+        Self { flash }
+    }
+
+    pub async fn load(&self) -> Result<Config, ConfigError> {
+        let mut buf = [0; RECORD_SIZE];
+
+        self.flash
+            .lock()
+            .await
+            .blocking_read(Self::OFFSET, &mut buf)
+            .map_err(|_| ConfigError::Storage)?;
+
+        Config::decode(&buf)
+    }
+
+    /// Loads the stored configuration falling back to the defaults if there isn't a valid one.
+    pub async fn load_or_default(&self) -> &'static Config {
+        static CONFIG: StaticCell<Config> = StaticCell::new();
+
+        CONFIG.init(match self.load().await {
+            Ok(config) => config,
+            Err(ConfigError::Blank) => {
+                info!("No stored configuration, using defaults");
+                Config::default()
+            }
+            Err(e) => {
+                warn!("Failed to load configuration ({e:?}), using defaults");
+                Config::default()
+            }
+        })
+    }
+
+    /// Writes the configuration to flash, it is used from the next boot.
+    pub async fn save(&self, config: &Config) -> Result<(), ConfigError> {
+        let mut buf = [0xFF; RECORD_SIZE];
+        config.encode(&mut buf)?;
+
+        let mut flash = self.flash.lock().await;
+        flash
+            .blocking_erase(Self::OFFSET, Self::OFFSET + ERASE_SIZE as u32)
+            .map_err(|_| ConfigError::Storage)?;
+        flash
+            .blocking_write(Self::OFFSET, &buf)
+            .map_err(|_| ConfigError::Storage)?;
+
+        Ok(())
+    }
+}
@@ -0,0 +1,276 @@
+//! CYW43 Wi-Fi bring-up and the network stack, shared by the Pico W and Pico 2 W.
+
+use cyw43::{Control, JoinOptions};
+use cyw43_pio::PioSpi;
+use embassy_executor::Spawner;
+use embassy_futures::select::{select, select3, Either, Either3};
+use embassy_net::{Config, ConfigV4, Ipv4Cidr, Stack, StackResources, StaticConfigV4};
+use embassy_rp::{
+    bind_interrupts,
+    clocks::RoscRng,
+    gpio::{Input, Level, Output},
+    peripherals::{DMA_CH0, PIN_23, PIN_24, PIN_25, PIN_29, PIO0},
+    pio::{InterruptHandler, Pio},
+};
+use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, signal::Signal};
+use embassy_time::{Duration, Instant, Timer};
+use heapless::String;
+use log::{error, info, warn};
+use rand::RngCore;
+use static_cell::StaticCell;
+
+use crate::{
+    board::Board,
+    config::{Config as DeviceConfig, MAX_SSID_LEN},
+    provisioning::{dhcp::dhcp_task, dns::dns_task, start_provisioning, AP_ADDRESS, AP_PREFIX_LEN},
+};
+
+static LED_STATE: Signal<CriticalSectionRawMutex, bool> = Signal::new();
+
+/// Signalled when the setup button has been held.
+static SETUP_REQUESTED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// How long the setup button must be held to start the setup access point.
+const SETUP_HOLD: Duration = Duration::from_secs(5);
+
+/// The channel the setup access point runs on.
+const AP_CHANNEL: u8 = 6;
+
+/// How long the setup access point runs before rebooting to try the configured network again.
+const SETUP_TIMEOUT: Duration = Duration::from_secs(10 * 60);
+
+bind_interrupts!(struct Irqs {
+    PIO0_IRQ_0 => InterruptHandler<PIO0>;
+});
+
+/// The peripherals wired to the CYW43, these are the same on both boards.
+pub struct WifiPeripherals {
+    pub pwr: PIN_23,
+    pub dio: PIN_24,
+    pub cs: PIN_25,
+    pub clk: PIN_29,
+    pub pio: PIO0,
+    pub dma: DMA_CH0,
+}
+
+#[embassy_executor::task]
+async fn cyw43_task(
+    runner: cyw43::Runner<'static, Output<'static>, PioSpi<'static, PIO0, 0, DMA_CH0>>,
+) -> ! {
+    runner.run().await
+}
+
+#[embassy_executor::task]
+async fn embassy_net_task(
+    mut runner: embassy_net::Runner<'static, cyw43::NetDriver<'static>>,
+) -> ! {
+    runner.run().await
+}
+
+/// Runs the setup access point until the device is rebooted with new settings. When a network is
+/// configured the device reboots after `SETUP_TIMEOUT` so it recovers from the network being down
+/// for a while.
+async fn provision(
+    control: &mut Control<'static>,
+    network: Stack<'static>,
+    ssid: &str,
+    configured: bool,
+) -> ! {
+    info!("Starting the setup access point {ssid}");
+    let timeout = configured.then(|| Instant::now() + SETUP_TIMEOUT);
+
+    network.set_config_v4(ConfigV4::Static(StaticConfigV4 {
+        address: Ipv4Cidr::new(AP_ADDRESS, AP_PREFIX_LEN),
+        gateway: None,
+        dns_servers: Default::default(),
+    }));
+    control.start_ap_open(ssid, AP_CHANNEL).await;
+    start_provisioning();
+
+    let spawner = Spawner::for_current_executor().await;
+    spawner.spawn(dhcp_task(network)).unwrap();
+    spawner.spawn(dns_task(network)).unwrap();
+
+    // Blink the LED to show the device is waiting to be set up.
+    loop {
+        control.gpio_set(0, true).await;
+        Timer::after_millis(500).await;
+        control.gpio_set(0, false).await;
+        Timer::after_millis(500).await;
+
+        if timeout.is_some_and(|timeout| Instant::now() >= timeout) {
+            info!("Setup timed out, restarting to join the network");
+            Board::reboot();
+        }
+    }
+}
+
+/// Joins the network, falling back to the setup access point when there is no network configured,
+/// after `join_attempts` failed attempts before the network is first joined or when the setup
+/// button is held. Once the network has been joined it is rejoined for as long as it takes.
+#[embassy_executor::task]
+async fn wifi_task(
+    mut control: Control<'static>,
+    network: Stack<'static>,
+    config: &'static DeviceConfig,
+    ap_ssid: &'static str,
+) -> ! {
+    let configured = !config.ssid.is_empty();
+    if !configured {
+        warn!("No network configured");
+        provision(&mut control, network, ap_ssid, configured).await;
+    }
+
+    let mut joined = false;
+    loop {
+        let mut failures: u32 = 0;
+        loop {
+            if SETUP_REQUESTED.signaled() {
+                provision(&mut control, network, ap_ssid, configured).await;
+            }
+
+            match control
+                .join(&config.ssid, JoinOptions::new(config.password.as_bytes()))
+                .await
+            {
+                Ok(_) => {
+                    info!("Connected to wifi");
+                    joined = true;
+                    break;
+                }
+                Err(err) => {
+                    error!("Failed to join network: {}", err.status);
+
+                    failures += 1;
+                    if !joined
+                        && config.join_attempts > 0
+                        && failures >= u32::from(config.join_attempts)
+                    {
+                        warn!("Giving up joining the network after {failures} attempts");
+                        provision(&mut control, network, ap_ssid, configured).await;
+                    }
+
+                    Timer::after_secs(1).await;
+                }
+            }
+        }
+
+        network.wait_link_up().await;
+
+        loop {
+            match select3(
+                network.wait_link_down(),
+                LED_STATE.wait(),
+                SETUP_REQUESTED.wait(),
+            )
+            .await
+            {
+                Either3::First(_) => {
+                    break;
+                }
+                Either3::Second(state) => {
+                    control.gpio_set(0, state).await;
+                }
+                Either3::Third(_) => {
+                    control.leave().await;
+                    provision(&mut control, network, ap_ssid, configured).await;
+                }
+            }
+        }
+
+        control.gpio_set(0, false).await;
+        warn!("Lost wifi connection");
+
+        control.leave().await;
+    }
+}
+
+/// Starts the setup access point once the setup button has been held, a button between the pin
+/// and ground.
+#[embassy_executor::task]
+async fn setup_button_task(mut button: Input<'static>) -> ! {
+    loop {
+        button.wait_for_low().await;
+        if let Either::Second(_) = select(button.wait_for_high(), Timer::after(SETUP_HOLD)).await {
+            info!("Setup button held");
+            SETUP_REQUESTED.signal(());
+        }
+        button.wait_for_high().await;
+    }
+}
+
+/// The status LED, which is attached to the CYW43 rather than the microcontroller.
+#[derive(Clone, Copy)]
+pub struct Led;
+
+impl Led {
+    pub async fn set(&self, state: bool) {
+        LED_STATE.signal(state);
+    }
+}
+
+/// Brings up the CYW43 and the network stack and starts joining the network.
+pub async fn init(
+    spawner: &Spawner,
+    peripherals: WifiPeripherals,
+    setup_button: Input<'static>,
+    config: &'static DeviceConfig,
+    board_id: &str,
+) -> Stack<'static> {
+    let fw = include_bytes!("../../cyw43/43439A0.bin");
+    let clm = include_bytes!("../../cyw43/43439A0_clm.bin");
+
+    let pwr = Output::new(peripherals.pwr, Level::Low);
+    let cs = Output::new(peripherals.cs, Level::High);
+    let mut pio = Pio::new(peripherals.pio, Irqs);
+    let spi = PioSpi::new(
+        &mut pio.common,
+        pio.sm0,
+        pio.irq0,
+        cs,
+        peripherals.dio,
+        peripherals.clk,
+        peripherals.dma,
+    );
+
+    static STATE: StaticCell<cyw43::State> = StaticCell::new();
+    let state = STATE.init(cyw43::State::new());
+    let (net_device, mut control, runner) = cyw43::new(state, pwr, spi, fw).await;
+
+    spawner.spawn(cyw43_task(runner)).unwrap();
+
+    control.init(clm).await;
+    control
+        .set_power_management(cyw43::PowerManagementMode::PowerSave)
+        .await;
+
+    let mut rng = RoscRng;
+    let seed = rng.next_u64();
+
+    let config = Config::dhcpv4(Default::default());
+
+    static RESOURCES: StaticCell<StackResources<9>> = StaticCell::new();
+    let (network, runner) = embassy_net::new(
+        net_device,
+        config,
+        RESOURCES.init(StackResources::new()),
+        seed,
+    );
+
+    spawner.spawn(embassy_net_task(runner)).unwrap();
+
+    static AP_SSID: StaticCell<String<MAX_SSID_LEN>> = StaticCell::new();
+    let ap_ssid = AP_SSID.init_with(|| {
+        let mut ssid = String::new();
+        let _ = ssid.push_str("blinky-");
+        let _ = ssid.push_str(board_id);
+        ssid
+    });
+
+    spawner
+        .spawn(wifi_task(control, network, config, ap_ssid))
+        .unwrap();
+    spawner.spawn(setup_button_task(setup_button)).unwrap();
+
+    network
+}
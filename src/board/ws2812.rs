@@ -7,10 +7,10 @@ use embassy_rp::{
     bind_interrupts,
     clocks::clk_sys_freq,
     dma::AnyChannel,
-    peripherals::{DMA_CH1, PIN_15, PIO1},
+    peripherals::{PIN_12, PIN_13, PIN_14, PIN_15, PIO1},
     pio::{
-        Common, Config, FifoJoin, Instance, InterruptHandler, LoadedProgram, Pio, ShiftConfig,
-        ShiftDirection, StateMachine,
+        Common, Config, FifoJoin, Instance, InterruptHandler, LoadedProgram, Pio, PioPin,
+        ShiftConfig, ShiftDirection, StateMachine,
     },
 };
 use embassy_rp::{Peripheral, PeripheralRef};
@@ -58,24 +58,58 @@ impl<'a, PIO: Instance> PioWs2812Program<'a, PIO> {
     }
 }
 
-/// Pio backed ws2812 driver
-pub struct Ws2812 {
+/// Pio backed ws2812 driver, one per state machine of PIO1.
+pub struct Ws2812<const SM: usize> {
     dma: PeripheralRef<'static, AnyChannel>,
-    sm: StateMachine<'static, PIO1, 0>,
+    sm: StateMachine<'static, PIO1, SM>,
+    cfg: Config<'static, PIO1>,
 }
 
-impl Ws2812 {
-    /// Configure a pio state machine to use the loaded ws2812 program.
-    pub fn new(pio: PIO1, dma: DMA_CH1, pin: PIN_15) -> Self {
+/// The pins driving each output.
+pub struct Ws2812Pins {
+    pub pin0: PIN_15,
+    pub pin1: PIN_14,
+    pub pin2: PIN_13,
+    pub pin3: PIN_12,
+}
+
+/// A driver for each of the outputs, sharing a single copy of the program.
+pub struct Ws2812Outputs(pub Ws2812<0>, pub Ws2812<1>, pub Ws2812<2>, pub Ws2812<3>);
+
+impl Ws2812Outputs {
+    pub fn new(pio: PIO1, dma: [AnyChannel; 4], pins: Ws2812Pins) -> Self {
         let Pio {
             mut common,
-            mut sm0,
+            sm0,
+            sm1,
+            sm2,
+            sm3,
             ..
         } = Pio::new(pio, Irqs);
 
+        let program = PioWs2812Program::new(&mut common);
+        let [dma0, dma1, dma2, dma3] = dma;
+
+        Self(
+            Ws2812::new(&mut common, &program, sm0, dma0, pins.pin0),
+            Ws2812::new(&mut common, &program, sm1, dma1, pins.pin1),
+            Ws2812::new(&mut common, &program, sm2, dma2, pins.pin2),
+            Ws2812::new(&mut common, &program, sm3, dma3, pins.pin3),
+        )
+    }
+}
+
+impl<const SM: usize> Ws2812<SM> {
+    /// Configure a pio state machine to use the loaded ws2812 program.
+    fn new(
+        common: &mut Common<'static, PIO1>,
+        program: &PioWs2812Program<'static, PIO1>,
+        mut sm: StateMachine<'static, PIO1, SM>,
+        dma: AnyChannel,
+        pin: impl PioPin,
+    ) -> Self {
         let dma = dma.into_ref();
 
-        // Setup sm0
         let mut cfg = Config::default();
 
         // Pin config
@@ -83,7 +117,6 @@ impl Ws2812 {
         cfg.set_out_pins(&[&out_pin]);
         cfg.set_set_pins(&[&out_pin]);
 
-        let program = PioWs2812Program::new(&mut common);
         cfg.use_program(&program.prg, &[&out_pin]);
 
         // Clock config, measured in kHz to avoid overflows
@@ -100,16 +133,25 @@ impl Ws2812 {
             direction: ShiftDirection::Left,
         };
 
-        sm0.set_config(&cfg);
-        sm0.set_enable(true);
+        sm.set_config(&cfg);
+        sm.set_enable(true);
 
-        Self {
-            dma: dma.map_into(),
-            sm: sm0,
+        Self { dma, sm, cfg }
+    }
+
+    /// Sets the number of bits sent for each pixel, 24 for RGB strips and 32 for RGBW.
+    pub fn set_pixel_bits(&mut self, bits: u8) {
+        if self.cfg.shift_out.threshold == bits {
+            return;
         }
+
+        self.cfg.shift_out.threshold = bits;
+        self.sm.set_enable(false);
+        self.sm.set_config(&self.cfg);
+        self.sm.set_enable(true);
     }
 
-    pub async fn write<const N: usize>(&mut self, data: &[u32; N]) {
+    pub async fn write(&mut self, data: &[u32]) {
         // DMA transfer
         self.sm.tx().dma_push(self.dma.reborrow(), data).await;
 
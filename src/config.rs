@@ -0,0 +1,685 @@
+//! The persistent device configuration.
+//!
+//! The configuration is stored as a single record laid out as:
+//!
+//! | Bytes | Contents                                     |
+//! |-------|----------------------------------------------|
+//! | 4     | Magic, `BLNK`                                |
+//! | 2     | Format version, little endian                |
+//! | 2     | Payload length, little endian                |
+//! | n     | Payload, fields in the order of their version |
+//! | 4     | CRC-32 of everything before it               |
+//!
+//! Fields are only ever appended to the payload so a record written by an older firmware decodes
+//! by reading the fields it has and falling back to defaults for the rest.
+
+use heapless::{String, Vec};
+
+use crate::{
+    leds::{ColorOrder, Segment, WhiteChannel, MAX_OUTPUTS, MAX_PIXELS, MAX_SEGMENTS},
+    realtime::DmxMapping,
+};
+
+pub const MAX_SSID_LEN: usize = 32;
+pub const MAX_PASSWORD_LEN: usize = 64;
+pub const MAX_BROKER_LEN: usize = 64;
+pub const MAX_SEGMENT_NAME_LEN: usize = 16;
+
+/// The largest encoded record, must fit within a single flash sector.
+pub const RECORD_SIZE: usize = 512;
+
+const MAGIC: [u8; 4] = *b"BLNK";
+const HEADER_LEN: usize = 8;
+
+/// The current format version, bump this whenever fields are added to the payload.
+pub const VERSION: u16 = 12;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ConfigError {
+    /// The storage has never had a record written to it.
+    Blank,
+    BadMagic,
+    /// The record was written by a newer firmware.
+    UnsupportedVersion(u16),
+    BadChecksum,
+    /// The record or one of its fields ends early.
+    Truncated,
+    /// A string field holds invalid UTF-8 or is too long.
+    InvalidField,
+    /// The buffer is too small to hold the encoded record.
+    BufferTooSmall,
+    /// The underlying storage failed to read or write.
+    Storage,
+}
+
+/// The layout of the strip attached to one output.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct OutputConfig {
+    /// 0 disables the output.
+    pub pixels: u16,
+    pub color_order: ColorOrder,
+    /// Set for RGBW strips. Added in version 6.
+    pub white: Option<WhiteChannel>,
+}
+
+impl OutputConfig {
+    const DISABLED: Self = Self {
+        pixels: 0,
+        color_order: ColorOrder::Rgb,
+        white: None,
+    };
+
+    pub fn is_enabled(&self) -> bool {
+        self.pixels > 0
+    }
+}
+
+/// A named run of pixels on one output, controlled as its own light.
+#[derive(Clone, PartialEq, Eq)]
+pub struct SegmentConfig {
+    pub name: String<MAX_SEGMENT_NAME_LEN>,
+    pub output: u8,
+    pub start: u16,
+    pub pixels: u16,
+    pub reverse: bool,
+}
+
+#[derive(Clone, PartialEq, Eq)]
+pub struct Config {
+    pub ssid: String<MAX_SSID_LEN>,
+    pub password: String<MAX_PASSWORD_LEN>,
+    pub broker: String<MAX_BROKER_LEN>,
+    /// The first output was added in version 2, the rest in version 5.
+    pub outputs: [OutputConfig; MAX_OUTPUTS],
+    /// The transition used when Home Assistant doesn't request one. Added in version 3.
+    pub transition_ms: u16,
+    /// The current drawn by one channel at full intensity. Added in version 4.
+    pub ma_per_channel: u16,
+    /// The most current the strip may draw, 0 disables limiting. Added in version 4.
+    pub power_budget_ma: u16,
+    /// The coolest colour temperature Home Assistant may select, in mireds. Added in version 7.
+    pub min_mireds: u16,
+    /// The warmest colour temperature Home Assistant may select, in mireds. Added in version 7.
+    pub max_mireds: u16,
+    /// Empty when each output is a single segment. Added in version 8.
+    pub segments: Vec<SegmentConfig, MAX_SEGMENTS>,
+    /// The E1.31 universe holding the first pixel, 0 ignores E1.31. Added in version 9.
+    pub e131_universe: u16,
+    /// The channel of the first pixel in its E1.31 universe, from 1. Added in version 9.
+    pub e131_start_channel: u16,
+    /// How long a realtime stream is shown after its last packet. Added in version 9.
+    pub realtime_timeout_ms: u16,
+    /// Whether Art-Net is shown. Added in version 10.
+    pub artnet_enabled: bool,
+    /// The Art-Net port address holding the first pixel. Added in version 10.
+    pub artnet_universe: u16,
+    /// The channel of the first pixel in its Art-Net universe, from 1. Added in version 10.
+    pub artnet_start_channel: u16,
+    /// Failed attempts to join the network after booting before falling back to the setup access
+    /// point, 0 keeps trying forever. Added in version 11.
+    pub join_attempts: u8,
+    /// Whether DDP is shown. Added in version 12.
+    pub ddp_enabled: bool,
+}
+
+fn default_string<const N: usize>(value: Option<&str>) -> String<N> {
+    let mut string = String::new();
+    if let Some(value) = value {
+        let _ = string.push_str(value);
+    }
+    string
+}
+
+impl Default for Config {
+    /// Uses any values provided at build time, these are only used until a configuration is
+    /// stored on the device.
+    fn default() -> Self {
+        Self {
+            ssid: default_string(option_env!("BLINKY_SSID")),
+            password: default_string(option_env!("BLINKY_PASSWORD")),
+            broker: default_string(option_env!("BLINKY_BROKER")),
+            outputs: [
+                OutputConfig {
+                    pixels: 50,
+                    color_order: ColorOrder::Rgb,
+                    white: None,
+                },
+                OutputConfig::DISABLED,
+                OutputConfig::DISABLED,
+                OutputConfig::DISABLED,
+            ],
+            transition_ms: 500,
+            ma_per_channel: 20,
+            // Leaves headroom for the board itself on a 500mA USB port.
+            power_budget_ma: 400,
+            // 6500K to 2000K.
+            min_mireds: 153,
+            max_mireds: 500,
+            segments: Vec::new(),
+            e131_universe: 0,
+            e131_start_channel: 1,
+            realtime_timeout_ms: 2500,
+            artnet_enabled: false,
+            artnet_universe: 0,
+            artnet_start_channel: 1,
+            join_attempts: 10,
+            ddp_enabled: false,
+        }
+    }
+}
+
+/// CRC-32 (IEEE 802.3) as used by zlib.
+pub fn crc32(data: &[u8]) -> u32 {
+    let mut crc = 0xFFFF_FFFF_u32;
+
+    for byte in data {
+        crc ^= u32::from(*byte);
+        for _ in 0..8 {
+            let mask = (crc & 1).wrapping_neg();
+            crc = (crc >> 1) ^ (0xEDB8_8320 & mask);
+        }
+    }
+
+    !crc
+}
+
+struct Writer<'a> {
+    buf: &'a mut [u8],
+    cursor: usize,
+}
+
+impl Writer<'_> {
+    fn bytes(&mut self, bytes: &[u8]) -> Result<(), ConfigError> {
+        let end = self.cursor + bytes.len();
+        if end > self.buf.len() {
+            return Err(ConfigError::BufferTooSmall);
+        }
+
+        self.buf[self.cursor..end].copy_from_slice(bytes);
+        self.cursor = end;
+        Ok(())
+    }
+
+    fn u8(&mut self, value: u8) -> Result<(), ConfigError> {
+        self.bytes(&[value])
+    }
+
+    fn u16(&mut self, value: u16) -> Result<(), ConfigError> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn u32(&mut self, value: u32) -> Result<(), ConfigError> {
+        self.bytes(&value.to_le_bytes())
+    }
+
+    fn str(&mut self, value: &str) -> Result<(), ConfigError> {
+        let len = u8::try_from(value.len()).map_err(|_| ConfigError::InvalidField)?;
+        self.u8(len)?;
+        self.bytes(value.as_bytes())
+    }
+
+    fn output(&mut self, output: &OutputConfig) -> Result<(), ConfigError> {
+        self.u16(output.pixels)?;
+        self.u8(output.color_order.to_u8())
+    }
+
+    fn white(&mut self, white: Option<WhiteChannel>) -> Result<(), ConfigError> {
+        self.u8(white.is_some().into())?;
+        self.u16(white.map(|white| white.kelvin()).unwrap_or_default())
+    }
+
+    fn segment(&mut self, segment: &SegmentConfig) -> Result<(), ConfigError> {
+        self.str(&segment.name)?;
+        self.u8(segment.output)?;
+        self.u16(segment.start)?;
+        self.u16(segment.pixels)?;
+        self.u8(segment.reverse.into())
+    }
+}
+
+struct Reader<'a> {
+    buf: &'a [u8],
+    cursor: usize,
+}
+
+impl<'a> Reader<'a> {
+    fn bytes(&mut self, len: usize) -> Result<&'a [u8], ConfigError> {
+        let end = self.cursor + len;
+        if end > self.buf.len() {
+            return Err(ConfigError::Truncated);
+        }
+
+        let bytes = &self.buf[self.cursor..end];
+        self.cursor = end;
+        Ok(bytes)
+    }
+
+    fn u8(&mut self) -> Result<u8, ConfigError> {
+        Ok(self.bytes(1)?[0])
+    }
+
+    fn u16(&mut self) -> Result<u16, ConfigError> {
+        let bytes = self.bytes(2)?;
+        Ok(u16::from_le_bytes([bytes[0], bytes[1]]))
+    }
+
+    fn u32(&mut self) -> Result<u32, ConfigError> {
+        let bytes = self.bytes(4)?;
+        Ok(u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]))
+    }
+
+    fn str<const N: usize>(&mut self) -> Result<String<N>, ConfigError> {
+        let len = self.u8()? as usize;
+        let bytes = self.bytes(len)?;
+        let value = core::str::from_utf8(bytes).map_err(|_| ConfigError::InvalidField)?;
+
+        let mut string = String::new();
+        string
+            .push_str(value)
+            .map_err(|_| ConfigError::InvalidField)?;
+        Ok(string)
+    }
+
+    fn output(&mut self) -> Result<OutputConfig, ConfigError> {
+        Ok(OutputConfig {
+            pixels: self.u16()?.min(MAX_PIXELS as u16),
+            color_order: ColorOrder::from_u8(self.u8()?).ok_or(ConfigError::InvalidField)?,
+            white: None,
+        })
+    }
+
+    fn white(&mut self) -> Result<Option<WhiteChannel>, ConfigError> {
+        let rgbw = self.u8()?;
+        let kelvin = self.u16()?;
+
+        match rgbw {
+            0 => Ok(None),
+            1 => Ok(Some(WhiteChannel::from_kelvin(kelvin))),
+            _ => Err(ConfigError::InvalidField),
+        }
+    }
+
+    fn segment(&mut self) -> Result<SegmentConfig, ConfigError> {
+        let segment = SegmentConfig {
+            name: self.str()?,
+            output: self.u8()?,
+            start: self.u16()?,
+            pixels: self.u16()?,
+            reverse: match self.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(ConfigError::InvalidField),
+            },
+        };
+
+        if segment.output as usize >= MAX_OUTPUTS {
+            return Err(ConfigError::InvalidField);
+        }
+
+        Ok(segment)
+    }
+}
+
+impl Config {
+    /// Encodes the record into `buf` at the current version, returning the encoded length.
+    pub fn encode(&self, buf: &mut [u8]) -> Result<usize, ConfigError> {
+        let mut writer = Writer { buf, cursor: 0 };
+
+        writer.bytes(&MAGIC)?;
+        writer.u16(VERSION)?;
+        // Placeholder for the payload length.
+        writer.u16(0)?;
+
+        writer.str(&self.ssid)?;
+        writer.str(&self.password)?;
+        writer.str(&self.broker)?;
+        writer.output(&self.outputs[0])?;
+        writer.u16(self.transition_ms)?;
+        writer.u16(self.ma_per_channel)?;
+        writer.u16(self.power_budget_ma)?;
+        for output in &self.outputs[1..] {
+            writer.output(output)?;
+        }
+        for output in &self.outputs {
+            writer.white(output.white)?;
+        }
+        writer.u16(self.min_mireds)?;
+        writer.u16(self.max_mireds)?;
+        writer.u8(self.segments.len() as u8)?;
+        for segment in &self.segments {
+            writer.segment(segment)?;
+        }
+        writer.u16(self.e131_universe)?;
+        writer.u16(self.e131_start_channel)?;
+        writer.u16(self.realtime_timeout_ms)?;
+        writer.u8(self.artnet_enabled.into())?;
+        writer.u16(self.artnet_universe)?;
+        writer.u16(self.artnet_start_channel)?;
+        writer.u8(self.join_attempts)?;
+        writer.u8(self.ddp_enabled.into())?;
+
+        let payload_len =
+            u16::try_from(writer.cursor - HEADER_LEN).map_err(|_| ConfigError::BufferTooSmall)?;
+        writer.buf[6..8].copy_from_slice(&payload_len.to_le_bytes());
+
+        let crc = crc32(&writer.buf[..writer.cursor]);
+        writer.u32(crc)?;
+
+        Ok(writer.cursor)
+    }
+
+    /// Decodes a record of any supported version. Fields missing from older versions are
+    /// migrated by taking their default values.
+    pub fn decode(buf: &[u8]) -> Result<Self, ConfigError> {
+        let mut reader = Reader { buf, cursor: 0 };
+
+        let magic = reader.bytes(MAGIC.len())?;
+        if magic.iter().all(|b| *b == 0xFF) {
+            return Err(ConfigError::Blank);
+        }
+        if magic != MAGIC {
+            return Err(ConfigError::BadMagic);
+        }
+
+        let version = reader.u16()?;
+        if version == 0 || version > VERSION {
+            return Err(ConfigError::UnsupportedVersion(version));
+        }
+
+        let payload_len = reader.u16()? as usize;
+        reader.bytes(payload_len)?;
+        let expected = crc32(&buf[..reader.cursor]);
+        if reader.u32()? != expected {
+            return Err(ConfigError::BadChecksum);
+        }
+
+        let mut payload = Reader {
+            buf: &buf[HEADER_LEN..HEADER_LEN + payload_len],
+            cursor: 0,
+        };
+        let mut config = Config {
+            ssid: payload.str()?,
+            password: payload.str()?,
+            broker: payload.str()?,
+            ..Config::default()
+        };
+
+        if version >= 2 {
+            config.outputs[0] = payload.output()?;
+        }
+
+        if version >= 3 {
+            config.transition_ms = payload.u16()?;
+        }
+
+        if version >= 4 {
+            config.ma_per_channel = payload.u16()?;
+            config.power_budget_ma = payload.u16()?;
+        }
+
+        if version >= 5 {
+            for output in &mut config.outputs[1..] {
+                *output = payload.output()?;
+            }
+        }
+
+        if version >= 6 {
+            for output in &mut config.outputs {
+                output.white = payload.white()?;
+            }
+        }
+
+        if version >= 7 {
+            let min_mireds = payload.u16()?;
+            let max_mireds = payload.u16()?;
+            // An empty range can't be clamped to, keep the defaults instead.
+            if min_mireds > 0 && min_mireds <= max_mireds {
+                config.min_mireds = min_mireds;
+                config.max_mireds = max_mireds;
+            }
+        }
+
+        if version >= 8 {
+            let count = payload.u8()? as usize;
+            if count > MAX_SEGMENTS {
+                return Err(ConfigError::InvalidField);
+            }
+            for _ in 0..count {
+                let _ = config.segments.push(payload.segment()?);
+            }
+        }
+
+        if version >= 9 {
+            config.e131_universe = payload.u16()?;
+            config.e131_start_channel = payload.u16()?;
+            config.realtime_timeout_ms = payload.u16()?;
+        }
+
+        if version >= 10 {
+            config.artnet_enabled = match payload.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(ConfigError::InvalidField),
+            };
+            config.artnet_universe = payload.u16()?;
+            config.artnet_start_channel = payload.u16()?;
+        }
+
+        if version >= 11 {
+            config.join_attempts = payload.u8()?;
+        }
+
+        if version >= 12 {
+            config.ddp_enabled = match payload.u8()? {
+                0 => false,
+                1 => true,
+                _ => return Err(ConfigError::InvalidField),
+            };
+        }
+
+        Ok(config)
+    }
+
+    /// Where a segment's pixels are, limited to the pixels its output has. Without configured
+    /// segments each output is a segment of its own.
+    pub fn segment(&self, index: usize) -> Segment {
+        let (output, start, pixels, reverse) = if self.segments.is_empty() {
+            match self.outputs.get(index) {
+                Some(output) => (index, 0, output.pixels, false),
+                None => return Segment::DISABLED,
+            }
+        } else {
+            match self.segments.get(index) {
+                Some(segment) => (
+                    segment.output as usize,
+                    segment.start,
+                    segment.pixels,
+                    segment.reverse,
+                ),
+                None => return Segment::DISABLED,
+            }
+        };
+
+        let available = self.outputs[output].pixels.saturating_sub(start);
+        Segment {
+            output,
+            start: start as usize,
+            pixels: pixels.min(available) as usize,
+            reverse,
+        }
+    }
+
+    /// Where the pixels are in the E1.31 universes, `None` when E1.31 is ignored.
+    pub fn e131(&self) -> Option<DmxMapping> {
+        (self.e131_universe > 0).then_some(DmxMapping {
+            universe: self.e131_universe,
+            start_channel: self.e131_start_channel,
+        })
+    }
+
+    /// Where the pixels are in the Art-Net universes, `None` when Art-Net is ignored.
+    pub fn artnet(&self) -> Option<DmxMapping> {
+        self.artnet_enabled.then_some(DmxMapping {
+            universe: self.artnet_universe,
+            start_channel: self.artnet_start_channel,
+        })
+    }
+
+    /// The name given to a configured segment.
+    pub fn segment_name(&self, index: usize) -> Option<&str> {
+        self.segments
+            .get(index)
+            .map(|segment| segment.name.as_str())
+            .filter(|name| !name.is_empty())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a record of any version around a hand written payload.
+    fn record(version: u16, payload: &[u8]) -> std::vec::Vec<u8> {
+        let mut record = std::vec::Vec::new();
+        record.extend_from_slice(&MAGIC);
+        record.extend_from_slice(&version.to_le_bytes());
+        record.extend_from_slice(&(payload.len() as u16).to_le_bytes());
+        record.extend_from_slice(payload);
+        let crc = crc32(&record);
+        record.extend_from_slice(&crc.to_le_bytes());
+        record
+    }
+
+    fn strings(values: &[&str]) -> std::vec::Vec<u8> {
+        let mut payload = std::vec::Vec::new();
+        for value in values {
+            payload.push(value.len() as u8);
+            payload.extend_from_slice(value.as_bytes());
+        }
+        payload
+    }
+
+    fn configured() -> Config {
+        let mut config = Config {
+            ssid: default_string(Some("network")),
+            password: default_string(Some("password")),
+            broker: default_string(Some("broker.local")),
+            transition_ms: 1200,
+            power_budget_ma: 2000,
+            e131_universe: 3,
+            artnet_enabled: true,
+            artnet_universe: 17,
+            join_attempts: 0,
+            ddp_enabled: true,
+            ..Config::default()
+        };
+        config.outputs[1] = OutputConfig {
+            pixels: 120,
+            color_order: ColorOrder::Grb,
+            white: Some(WhiteChannel::from_kelvin(4000)),
+        };
+        let _ = config.segments.push(SegmentConfig {
+            name: default_string(Some("shelf")),
+            output: 1,
+            start: 20,
+            pixels: 60,
+            reverse: true,
+        });
+        config
+    }
+
+    #[test]
+    fn crc32_matches_zlib() {
+        assert_eq!(crc32(b"123456789"), 0xCBF4_3926);
+    }
+
+    #[test]
+    fn round_trip() {
+        let config = configured();
+        let mut buf = [0; RECORD_SIZE];
+        let len = config.encode(&mut buf).unwrap();
+
+        assert!(Config::decode(&buf[..len]) == Ok(config.clone()));
+        // Trailing erased flash is ignored.
+        assert!(Config::decode(&buf) == Ok(config));
+    }
+
+    #[test]
+    fn rejects_bad_checksum() {
+        let mut buf = [0; RECORD_SIZE];
+        let len = configured().encode(&mut buf).unwrap();
+        buf[HEADER_LEN] ^= 0xFF;
+
+        assert!(Config::decode(&buf[..len]) == Err(ConfigError::BadChecksum));
+    }
+
+    #[test]
+    fn rejects_blank_and_foreign_records() {
+        assert!(Config::decode(&[0xFF; RECORD_SIZE]) == Err(ConfigError::Blank));
+        assert!(Config::decode(&[0; RECORD_SIZE]) == Err(ConfigError::BadMagic));
+        assert!(
+            Config::decode(&record(VERSION + 1, &[]))
+                == Err(ConfigError::UnsupportedVersion(VERSION + 1))
+        );
+    }
+
+    #[test]
+    fn rejects_truncated_payload() {
+        let payload = strings(&["network", "password"]);
+        assert!(Config::decode(&record(1, &payload)) == Err(ConfigError::Truncated));
+    }
+
+    #[test]
+    fn replaces_empty_mireds_range() {
+        let config = Config {
+            min_mireds: 400,
+            max_mireds: 200,
+            ..configured()
+        };
+        let mut buf = [0; RECORD_SIZE];
+        let len = config.encode(&mut buf).unwrap();
+
+        let decoded = Config::decode(&buf[..len]).unwrap();
+        assert_eq!(decoded.min_mireds, Config::default().min_mireds);
+        assert_eq!(decoded.max_mireds, Config::default().max_mireds);
+    }
+
+    #[test]
+    fn migrates_version_1() {
+        let payload = strings(&["network", "password", "broker.local"]);
+        let config = Config::decode(&record(1, &payload)).unwrap();
+
+        let expected = Config {
+            ssid: default_string(Some("network")),
+            password: default_string(Some("password")),
+            broker: default_string(Some("broker.local")),
+            ..Config::default()
+        };
+        assert!(config == expected);
+    }
+
+    #[test]
+    fn migrates_version_4() {
+        let mut payload = strings(&["network", "password", "broker.local"]);
+        // The first output, then the transition and power settings.
+        payload.extend_from_slice(&90_u16.to_le_bytes());
+        payload.push(ColorOrder::Grb.to_u8());
+        payload.extend_from_slice(&800_u16.to_le_bytes());
+        payload.extend_from_slice(&15_u16.to_le_bytes());
+        payload.extend_from_slice(&1000_u16.to_le_bytes());
+
+        let config = Config::decode(&record(4, &payload)).unwrap();
+        assert_eq!(config.outputs[0].pixels, 90);
+        assert!(config.outputs[0].color_order == ColorOrder::Grb);
+        assert!(config.outputs[0].white.is_none());
+        assert_eq!(config.transition_ms, 800);
+        assert_eq!(config.ma_per_channel, 15);
+        assert_eq!(config.power_budget_ma, 1000);
+        // Everything added later takes its default.
+        assert!(config.outputs[1] == OutputConfig::DISABLED);
+        assert!(config.segments.is_empty());
+        assert_eq!(config.realtime_timeout_ms, 2500);
+        assert_eq!(config.join_attempts, Config::default().join_attempts);
+        assert!(!config.ddp_enabled);
+    }
+}
@@ -0,0 +1,241 @@
+//! Home Assistant components needing more of the MQTT discovery schema than mcutie's own.
+
+use core::{fmt::Write, ops::Deref};
+
+use heapless::String;
+use log::warn;
+use mcutie::{
+    homeassistant::{light::SupportedColorMode, Component, Entity},
+    Error, Publishable, Topic,
+};
+use serde::{ser::SerializeStruct, Serialize, Serializer};
+
+use crate::{buffer::ByteBuffer, leds::ReportedState};
+
+/// Removes an entity from Home Assistant by replacing its retained discovery payload with an empty
+/// one.
+pub async fn remove_discovery<const A: usize, C: Component>(
+    entity: &Entity<'_, A, C>,
+    device_id: &str,
+) -> Result<(), Error> {
+    let mut topic = String::<128>::new();
+    if write!(
+        topic,
+        "homeassistant/{}/{device_id}/{}/config",
+        C::platform(),
+        entity.object_id
+    )
+    .is_err()
+    {
+        warn!("Discovery topic for {} is too long", entity.object_id);
+        return Ok(());
+    }
+
+    Topic::General(topic.as_str())
+        .with_bytes(b"")
+        .retain(true)
+        .publish()
+        .await
+}
+
+/// A JSON schema light that also advertises brightness, transitions and its colour temperature
+/// range.
+pub struct LedLight<'a, const C: usize, const E: usize> {
+    pub command_topic: Topic<&'a str>,
+    pub supported_color_modes: [SupportedColorMode; C],
+    pub effects: [&'a str; E],
+    /// The coolest colour temperature accepted, in mireds.
+    pub min_mireds: u16,
+    /// The warmest colour temperature accepted, in mireds.
+    pub max_mireds: u16,
+}
+
+impl<const C: usize, const E: usize> Serialize for LedLight<'_, C, E> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("LedLight", 9)?;
+        state.serialize_field("schema", "json")?;
+        state.serialize_field("command_topic", &self.command_topic)?;
+        state.serialize_field("brightness", &true)?;
+        state.serialize_field(
+            "supported_color_modes",
+            self.supported_color_modes.as_slice(),
+        )?;
+        state.serialize_field("effect", &(E > 0))?;
+        state.serialize_field("effect_list", self.effects.as_slice())?;
+        state.serialize_field("transition", &true)?;
+        state.serialize_field("min_mireds", &self.min_mireds)?;
+        state.serialize_field("max_mireds", &self.max_mireds)?;
+        state.end()
+    }
+}
+
+impl<const C: usize, const E: usize> Component for LedLight<'_, C, E> {
+    type State = ReportedState;
+
+    fn platform() -> &'static str {
+        "light"
+    }
+
+    async fn publish_state<T: Deref<Target = str>>(
+        &self,
+        topic: &Topic<T>,
+        state: Self::State,
+    ) -> Result<(), Error> {
+        let mut buffer = ByteBuffer::<256>::new();
+        if buffer.serialize(&state).is_err() {
+            warn!("Failed to encode light state");
+            return Ok(());
+        }
+
+        topic.with_bytes(buffer.buffer()).publish().await
+    }
+}
+
+/// A number that Home Assistant sets by sending a JSON payload, allowing several numbers to share
+/// command and state topics.
+pub struct Number<'a> {
+    pub command_topic: Topic<&'a str>,
+    /// Wraps the value into the command payload.
+    pub command_template: &'a str,
+    /// Extracts the value from the state payload.
+    pub value_template: &'a str,
+    pub min: u16,
+    pub max: u16,
+}
+
+impl Serialize for Number<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Number", 7)?;
+        state.serialize_field("command_topic", &self.command_topic)?;
+        state.serialize_field("command_template", self.command_template)?;
+        state.serialize_field("value_template", self.value_template)?;
+        state.serialize_field("min", &self.min)?;
+        state.serialize_field("max", &self.max)?;
+        state.serialize_field("step", &1)?;
+        state.serialize_field("mode", "slider")?;
+        state.end()
+    }
+}
+
+impl Component for Number<'_> {
+    type State = u16;
+
+    fn platform() -> &'static str {
+        "number"
+    }
+
+    async fn publish_state<T: Deref<Target = str>>(
+        &self,
+        topic: &Topic<T>,
+        state: Self::State,
+    ) -> Result<(), Error> {
+        let mut buffer = ByteBuffer::<8>::new();
+        let _ = write!(buffer, "{state}");
+
+        topic.with_bytes(buffer.buffer()).publish().await
+    }
+}
+
+/// A measurement shown with the device's diagnostics rather than its controls.
+pub struct DiagnosticSensor<'a> {
+    pub unit_of_measurement: Option<&'a str>,
+}
+
+impl Serialize for DiagnosticSensor<'_> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("DiagnosticSensor", 3)?;
+        state.serialize_field("entity_category", "diagnostic")?;
+        state.serialize_field("state_class", "measurement")?;
+        if let Some(unit) = self.unit_of_measurement {
+            state.serialize_field("unit_of_measurement", unit)?;
+        }
+        state.end()
+    }
+}
+
+impl Component for DiagnosticSensor<'_> {
+    type State = u32;
+
+    fn platform() -> &'static str {
+        "sensor"
+    }
+
+    async fn publish_state<T: Deref<Target = str>>(
+        &self,
+        topic: &Topic<T>,
+        state: Self::State,
+    ) -> Result<(), Error> {
+        let mut buffer = ByteBuffer::<16>::new();
+        let _ = write!(buffer, "{state}");
+
+        topic.with_bytes(buffer.buffer()).publish().await
+    }
+}
+
+/// A sensor whose state is one of a fixed list of options.
+pub struct EnumSensor<'a, const N: usize> {
+    pub options: [&'a str; N],
+}
+
+impl<const N: usize> Serialize for EnumSensor<'_, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("EnumSensor", 2)?;
+        state.serialize_field("device_class", "enum")?;
+        state.serialize_field("options", self.options.as_slice())?;
+        state.end()
+    }
+}
+
+impl<const N: usize> Component for EnumSensor<'_, N> {
+    type State = &'static str;
+
+    fn platform() -> &'static str {
+        "sensor"
+    }
+
+    async fn publish_state<T: Deref<Target = str>>(
+        &self,
+        topic: &Topic<T>,
+        state: Self::State,
+    ) -> Result<(), Error> {
+        topic.with_bytes(state.as_bytes()).publish().await
+    }
+}
+
+/// A choice from a list of options that Home Assistant sets by sending a JSON payload, like
+/// `Number`.
+pub struct Select<'a, const N: usize> {
+    pub command_topic: Topic<&'a str>,
+    /// Wraps the option into the command payload.
+    pub command_template: &'a str,
+    /// Extracts the option from the state payload.
+    pub value_template: &'a str,
+    pub options: [&'a str; N],
+}
+
+impl<const N: usize> Serialize for Select<'_, N> {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        let mut state = serializer.serialize_struct("Select", 4)?;
+        state.serialize_field("command_topic", &self.command_topic)?;
+        state.serialize_field("command_template", self.command_template)?;
+        state.serialize_field("value_template", self.value_template)?;
+        state.serialize_field("options", self.options.as_slice())?;
+        state.end()
+    }
+}
+
+impl<const N: usize> Component for Select<'_, N> {
+    type State = &'static str;
+
+    fn platform() -> &'static str {
+        "select"
+    }
+
+    async fn publish_state<T: Deref<Target = str>>(
+        &self,
+        topic: &Topic<T>,
+        state: Self::State,
+    ) -> Result<(), Error> {
+        topic.with_bytes(state.as_bytes()).publish().await
+    }
+}
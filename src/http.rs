@@ -0,0 +1,302 @@
+//! A minimal HTTP/1.1 server. Each connection carries a single request and is closed once the
+//! response has been sent, which is all the JSON API needs.
+
+use core::str;
+
+#[cfg(feature = "device")]
+use core::fmt::Write as _;
+
+#[cfg(feature = "device")]
+use embassy_net::{tcp::TcpSocket, Stack};
+#[cfg(feature = "device")]
+use embassy_time::Duration;
+#[cfg(feature = "device")]
+use embedded_io_async::Write;
+#[cfg(feature = "device")]
+use log::warn;
+
+#[cfg(feature = "device")]
+use crate::buffer::ByteBuffer;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Method {
+    Get,
+    Post,
+    Put,
+    Other,
+}
+
+pub struct Request<'a> {
+    pub method: Method,
+    /// The path without any query string.
+    pub path: &'a str,
+    pub body: &'a [u8],
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum HttpError {
+    /// The request line or headers can't be understood.
+    Malformed,
+}
+
+/// Parses a request from the bytes received so far, `Ok(None)` until all of it has arrived.
+pub fn parse_request(buf: &[u8]) -> Result<Option<Request<'_>>, HttpError> {
+    let Some(head_len) = buf.windows(4).position(|window| window == b"\r\n\r\n") else {
+        return Ok(None);
+    };
+    let head = str::from_utf8(&buf[..head_len]).map_err(|_| HttpError::Malformed)?;
+
+    let mut lines = head.split("\r\n");
+    let mut request_line = lines.next().unwrap_or_default().split(' ');
+    let method = match request_line.next() {
+        Some("GET") => Method::Get,
+        Some("POST") => Method::Post,
+        Some("PUT") => Method::Put,
+        Some(_) => Method::Other,
+        None => return Err(HttpError::Malformed),
+    };
+    let target = request_line.next().ok_or(HttpError::Malformed)?;
+    let path = target.split('?').next().unwrap_or(target);
+
+    let mut content_length = 0;
+    for line in lines {
+        let Some((name, value)) = line.split_once(':') else {
+            return Err(HttpError::Malformed);
+        };
+        if name.trim().eq_ignore_ascii_case("content-length") {
+            content_length = value.trim().parse().map_err(|_| HttpError::Malformed)?;
+        }
+    }
+
+    let body_start = head_len + 4;
+    let body_end = body_start
+        .checked_add(content_length)
+        .ok_or(HttpError::Malformed)?;
+    match buf.get(body_start..body_end) {
+        Some(body) => Ok(Some(Request { method, path, body })),
+        None => Ok(None),
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Status {
+    Ok,
+    BadRequest,
+    NotFound,
+    MethodNotAllowed,
+    PayloadTooLarge,
+    InternalServerError,
+}
+
+impl Status {
+    pub fn code(&self) -> u16 {
+        match self {
+            Self::Ok => 200,
+            Self::BadRequest => 400,
+            Self::NotFound => 404,
+            Self::MethodNotAllowed => 405,
+            Self::PayloadTooLarge => 413,
+            Self::InternalServerError => 500,
+        }
+    }
+
+    pub fn reason(&self) -> &'static str {
+        match self {
+            Self::Ok => "OK",
+            Self::BadRequest => "Bad Request",
+            Self::NotFound => "Not Found",
+            Self::MethodNotAllowed => "Method Not Allowed",
+            Self::PayloadTooLarge => "Payload Too Large",
+            Self::InternalServerError => "Internal Server Error",
+        }
+    }
+}
+
+pub struct Response<'a> {
+    pub status: Status,
+    pub content_type: &'a str,
+    /// Set when the body is compressed, such as `gzip`.
+    pub content_encoding: Option<&'a str>,
+    pub body: &'a [u8],
+}
+
+impl<'a> Response<'a> {
+    pub fn json(body: &'a [u8]) -> Self {
+        Self {
+            status: Status::Ok,
+            content_type: "application/json",
+            content_encoding: None,
+            body,
+        }
+    }
+
+    /// A gzip compressed body, browsers all accept it.
+    pub fn gzip(content_type: &'a str, body: &'a [u8]) -> Self {
+        Self {
+            status: Status::Ok,
+            content_type,
+            content_encoding: Some("gzip"),
+            body,
+        }
+    }
+
+    /// A response with just a status, the reason is sent as the body.
+    pub fn error(status: Status) -> Self {
+        Self {
+            status,
+            content_type: "text/plain",
+            content_encoding: None,
+            body: status.reason().as_bytes(),
+        }
+    }
+}
+
+/// Answers the requests made to a server.
+#[allow(async_fn_in_trait)]
+pub trait Handler {
+    async fn handle(&mut self, request: Request<'_>) -> Response<'_>;
+}
+
+#[cfg(feature = "device")]
+async fn write_response(
+    socket: &mut TcpSocket<'_>,
+    response: &Response<'_>,
+) -> Result<(), embassy_net::tcp::Error> {
+    let mut head = ByteBuffer::<256>::new();
+    let _ = write!(
+        head,
+        "HTTP/1.1 {} {}\r\n\
+        Content-Type: {}\r\n\
+        Content-Length: {}\r\n\
+        Access-Control-Allow-Origin: *\r\n\
+        Connection: close\r\n",
+        response.status.code(),
+        response.status.reason(),
+        response.content_type,
+        response.body.len(),
+    );
+    if let Some(encoding) = response.content_encoding {
+        let _ = write!(head, "Content-Encoding: {encoding}\r\n");
+    }
+    head.write(b"\r\n");
+
+    socket.write_all(head.buffer()).await?;
+    socket.write_all(response.body).await?;
+    socket.flush().await
+}
+
+/// Serves requests on `port` one connection at a time. Requests larger than `N` bytes are
+/// rejected.
+#[cfg(feature = "device")]
+pub async fn serve<H: Handler, const N: usize>(
+    network: Stack<'static>,
+    port: u16,
+    handler: &mut H,
+) -> ! {
+    let mut rx_buffer = [0; 1024];
+    let mut tx_buffer = [0; 1024];
+    let mut received = [0; N];
+
+    loop {
+        let mut socket = TcpSocket::new(network, &mut rx_buffer, &mut tx_buffer);
+        socket.set_timeout(Some(Duration::from_secs(10)));
+        if let Err(e) = socket.accept(port).await {
+            warn!("Failed to accept an HTTP connection: {e:?}");
+            continue;
+        }
+
+        let mut len = 0;
+        let response = loop {
+            if len == received.len() {
+                break Some(Response::error(Status::PayloadTooLarge));
+            }
+
+            match socket.read(&mut received[len..]).await {
+                Ok(0) | Err(_) => break None,
+                Ok(read) => len += read,
+            }
+
+            match parse_request(&received[..len]) {
+                Ok(Some(request)) => break Some(handler.handle(request).await),
+                Ok(None) => {}
+                Err(_) => break Some(Response::error(Status::BadRequest)),
+            }
+        };
+
+        if let Some(response) = response {
+            if let Err(e) = write_response(&mut socket, &response).await {
+                warn!("Failed to send an HTTP response: {e:?}");
+            }
+        }
+
+        socket.close();
+        // Let the close be sent before the buffers are reused.
+        let _ = socket.flush().await;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn parses_requests() {
+        let request = parse_request(b"GET /json/state?v=1 HTTP/1.1\r\nHost: blinky\r\n\r\n")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, Method::Get);
+        assert_eq!(request.path, "/json/state");
+        assert!(request.body.is_empty());
+
+        let request = parse_request(b"POST /json HTTP/1.1\r\ncontent-length: 2\r\n\r\n{}")
+            .unwrap()
+            .unwrap();
+        assert_eq!(request.method, Method::Post);
+        assert_eq!(request.body, b"{}");
+    }
+
+    #[test]
+    fn waits_for_the_head() {
+        assert!(parse_request(b"").unwrap().is_none());
+        assert!(parse_request(b"GET / HTTP/1.1\r\nHost: blinky\r\n")
+            .unwrap()
+            .is_none());
+    }
+
+    #[test]
+    fn waits_for_the_body() {
+        let head = b"PUT /api/settings HTTP/1.1\r\nContent-Length: 4\r\n\r\n";
+        assert!(parse_request(head).unwrap().is_none());
+        assert!(parse_request(&[head.as_slice(), b"{}"].concat())
+            .unwrap()
+            .is_none());
+
+        let full = [head.as_slice(), b"{}{}{}"].concat();
+        let request = parse_request(&full).unwrap().unwrap();
+        assert_eq!(request.body, b"{}{}");
+    }
+
+    #[test]
+    fn rejects_huge_content_lengths() {
+        let request = std::format!("POST / HTTP/1.1\r\nContent-Length: {}\r\n\r\n", usize::MAX);
+        assert_eq!(
+            parse_request(request.as_bytes()).err(),
+            Some(HttpError::Malformed)
+        );
+
+        let request = b"POST / HTTP/1.1\r\nContent-Length: 99999999999999999999999\r\n\r\n";
+        assert_eq!(parse_request(request).err(), Some(HttpError::Malformed));
+    }
+
+    #[test]
+    fn rejects_malformed_heads() {
+        for request in [
+            b"GET\r\n\r\n".as_slice(),
+            b"GET / HTTP/1.1\r\nNo colon\r\n\r\n",
+            b"POST / HTTP/1.1\r\nContent-Length: -1\r\n\r\n",
+            b"GET /\xFF HTTP/1.1\r\n\r\n",
+        ] {
+            assert_eq!(parse_request(request).err(), Some(HttpError::Malformed));
+        }
+    }
+}
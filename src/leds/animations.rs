@@ -1,22 +1,112 @@
-use embassy_rp::clocks::RoscRng;
+//! The built-in animations. Each renders the frame for a given time since the animation started
+//! using the current parameters, which can change between frames. Stateful animations use the
+//! previous frame or their own buffers to carry state between frames.
+
+use embassy_time::Duration;
 use rand::{distributions::Uniform, prelude::Distribution, Rng};
 
 use crate::{
-    board::Ws2812,
+    board,
     leds::{
-        color::{Float, Order, Pixel, HSV},
-        AbortableTicker,
+        blend::scale,
+        color::{Float, Pixel, HSV, RGB},
+        effects::EffectParams,
+        MAX_PIXELS,
     },
 };
 
-pub async fn flames<const N: usize, O: Order>(mut ticker: AbortableTicker, ws2812: &mut Ws2812) {
-    let mut pixels = [0_u32; N];
-    let min_hue: Float = 0.0;
-    let max_hue: Float = 50.0 / 360.0;
-    let uniform = Uniform::new_inclusive(min_hue, max_hue);
-    let mut rng = RoscRng;
+/// Something that draws frames onto a strip. Animations don't drive the strip themselves so they
+/// can be paced, layered or simulated by whatever calls them.
+pub trait Animation {
+    /// Called before the first frame with the length of the strip and the initial parameters.
+    fn init(&mut self, _len: usize, _params: &EffectParams) {}
+
+    /// Draws the frame for `elapsed` since the animation started. `pixels` holds the previous
+    /// frame.
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams);
+}
+
+/// Fractional bits of a `Clock` position.
+const FRACTION_BITS: u32 = 16;
+const ONE: u64 = 1 << FRACTION_BITS;
+
+/// Tracks how far an animation has progressed when its speed can change from frame to frame.
+#[derive(Default)]
+struct Clock {
+    last: Duration,
+    /// In steps, as fixed point with `FRACTION_BITS` fractional bits.
+    position: u64,
+    /// Whole steps already handed out by `due`.
+    done: u64,
+}
+
+impl Clock {
+    /// Moves on by the time since the previous frame at `interval_ms` per step, returning the
+    /// position as fixed point.
+    fn advance(&mut self, elapsed: Duration, interval_ms: u64) -> u64 {
+        let micros = (elapsed - self.last).as_micros();
+        self.last = elapsed;
+        self.position += micros * ONE / (interval_ms.max(1) * 1000);
+        self.position
+    }
+
+    /// The number of whole steps passed.
+    fn steps(&mut self, elapsed: Duration, interval_ms: u64) -> u64 {
+        self.advance(elapsed, interval_ms) >> FRACTION_BITS
+    }
+
+    /// How far through the current step, from 0 to 1.
+    fn fraction(&mut self, elapsed: Duration, interval_ms: u64) -> Float {
+        (self.advance(elapsed, interval_ms) % ONE) as Float / ONE as Float
+    }
+
+    /// The steps of a stateful animation due since the last frame, capped so a stalled animation
+    /// doesn't spend a long time catching up.
+    fn due(&mut self, elapsed: Duration, interval_ms: u64, max: u64) -> u64 {
+        let total = self.steps(elapsed, interval_ms);
+        let due = total - self.done;
+        self.done = total;
+        due.min(max)
+    }
+}
+
+fn hue(h: Float) -> RGB {
+    RGB::from_rgb(HSV { h, s: 1.0, v: 1.0 }.to_rgb())
+}
+
+/// The colour of pixel `i` of `len`, spreading the selected palette from the first pixel to the
+/// last.
+fn spread_color(params: &EffectParams, i: usize, len: usize) -> RGB {
+    let position = i * 255 / len.saturating_sub(1).max(1);
+    params.palette_color(position.min(255) as u8, || params.color)
+}
+
+/// Random warm colours. Intensity widens the range of hues from red towards yellow, or how much of
+/// the palette is used when one is selected. The colour is unused.
+#[derive(Default)]
+pub struct Flames {
+    clock: Clock,
+}
+
+impl Animation for Flames {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        // Redrawn every frame at the default speed, slower speeds hold each frame for longer.
+        if self.clock.due(elapsed, params.interval(10, 0), 1) == 0 {
+            return;
+        }
+
+        let mut rng = board::Rng;
+        if let Some(palette) = &params.palette {
+            for px in pixels.iter_mut() {
+                let color = palette.color_at(rng.gen_range(0..=params.intensity));
+                *px = scale(color, rng.gen());
+            }
+            return;
+        }
+
+        let max_hue: Float = Float::from(params.intensity) * 100.0 / (255.0 * 360.0);
+        let uniform = Uniform::new_inclusive(0.0, max_hue);
 
-    loop {
         for px in pixels.iter_mut() {
             let pixel = HSV {
                 h: uniform.sample(&mut rng),
@@ -24,13 +114,537 @@ pub async fn flames<const N: usize, O: Order>(mut ticker: AbortableTicker, ws281
                 v: rng.gen(),
             };
 
-            *px = pixel.to_word::<O>();
+            *px = RGB::from_rgb(pixel.to_rgb());
+        }
+    }
+}
+
+/// The hue range, or the selected palette, spread along the strip and cycling. Intensity sets how
+/// many times the range repeats, the colour is unused.
+#[derive(Default)]
+pub struct Rainbow {
+    clock: Clock,
+}
+
+impl Animation for Rainbow {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        let offset = self.clock.fraction(elapsed, params.interval(20_000, 1_000));
+        let spread = Float::from(params.intensity.max(1)) / 128.0;
+        let len = pixels.len() as Float;
+
+        for (i, px) in pixels.iter_mut().enumerate() {
+            let h = offset + i as Float * spread / len;
+            let h = h - (h as u32) as Float;
+            *px = params.palette_color((h * 255.0) as u8, || hue(h));
+        }
+    }
+}
+
+/// Fills the strip with the colour, or the palette spread along it, one pixel at a time, then
+/// clears it the same way. Intensity is unused.
+#[derive(Default)]
+pub struct ColorWipe {
+    clock: Clock,
+}
+
+impl Animation for ColorWipe {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        let len = pixels.len() as u64;
+        if len == 0 {
+            return;
+        }
+
+        let position = self.clock.steps(elapsed, params.interval(200, 5)) % (len * 2);
+        let (fill, filled) = if position < len {
+            (true, position + 1)
+        } else {
+            (false, position - len + 1)
+        };
+
+        for (i, px) in pixels[..filled as usize].iter_mut().enumerate() {
+            *px = if fill {
+                spread_color(params, i, len as usize)
+            } else {
+                RGB::default()
+            };
+        }
+    }
+}
+
+/// Evenly spaced pixels marching along the strip, coloured by the palette beneath them when one is
+/// selected. Intensity sets the spacing.
+#[derive(Default)]
+pub struct TheaterChase {
+    clock: Clock,
+}
+
+impl Animation for TheaterChase {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        let spacing = 2 + params.intensity as usize / 64;
+        let steps = self.clock.steps(elapsed, params.interval(500, 30));
+        let offset = (steps % spacing as u64) as usize;
+        let len = pixels.len();
+
+        for (i, px) in pixels.iter_mut().enumerate() {
+            *px = if i % spacing == offset {
+                spread_color(params, i, len)
+            } else {
+                RGB::default()
+            };
+        }
+    }
+}
+
+/// The whole strip slowly pulsing, in the colour or the palette spread along it. Intensity sets
+/// how far it dims.
+#[derive(Default)]
+pub struct Breathe {
+    clock: Clock,
+}
+
+impl Animation for Breathe {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        let phase = self.clock.fraction(elapsed, params.interval(10_000, 1_000));
+        let ramp = 2.0 * phase.min(1.0 - phase);
+
+        // Squared so the pulse lingers when dim, as the eye is most sensitive there.
+        let depth = Float::from(params.intensity);
+        let level = 255.0 - depth + depth * ramp * ramp;
+
+        let len = pixels.len();
+        for (i, px) in pixels.iter_mut().enumerate() {
+            *px = scale(spread_color(params, i, len), level as u8);
+        }
+    }
+}
+
+/// Random pixels flash the colour, or random colours from the palette, and then fade away.
+/// Intensity sets how many twinkle at once.
+#[derive(Default)]
+pub struct Twinkle {
+    clock: Clock,
+}
+
+impl Animation for Twinkle {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        if pixels.is_empty() {
+            return;
         }
 
-        ws2812.write(&pixels).await;
+        let mut rng = board::Rng;
+        for _ in 0..self.clock.due(elapsed, params.interval(100, 10), 50) {
+            for px in pixels.iter_mut() {
+                *px = scale(*px, 230);
+            }
+
+            // At the default intensity there is one new twinkle for every 40 pixels each step.
+            let rate = pixels.len() * params.intensity as usize;
+            let mut count = rate / 5120;
+            if rng.gen_range(0..5120) < rate % 5120 {
+                count += 1;
+            }
+
+            for _ in 0..count {
+                let index = rng.gen_range(0..pixels.len());
+                pixels[index] = params.palette_color(rng.gen(), || params.color);
+            }
+        }
+    }
+}
+
+/// A single bright head travelling along the strip with a fading tail, taking its colour from the
+/// palette beneath it when one is selected. Intensity sets the length of the tail.
+#[derive(Default)]
+pub struct Comet {
+    clock: Clock,
+}
+
+impl Animation for Comet {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        let len = pixels.len();
+        if len == 0 {
+            return;
+        }
+
+        let tail = (len * params.intensity as usize / 512).max(1);
+        let head = (self.clock.steps(elapsed, params.interval(150, 5)) % len as u64) as usize;
+
+        for (i, px) in pixels.iter_mut().enumerate() {
+            let behind = (head + len - i) % len;
+            *px = if behind < tail {
+                scale(
+                    spread_color(params, i, len),
+                    (255 - behind * 255 / tail) as u8,
+                )
+            } else {
+                RGB::default()
+            };
+        }
+    }
+}
+
+/// An eye sweeping back and forth with a short trail either side, taking its colour from the
+/// palette beneath it when one is selected. Intensity sets the width of the eye.
+#[derive(Default)]
+pub struct LarsonScanner {
+    clock: Clock,
+}
+
+impl Animation for LarsonScanner {
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        let len = pixels.len();
+        if len == 0 {
+            return;
+        }
+
+        let width = 1 + params.intensity as usize / 32;
+        let span = (len * 2).saturating_sub(2).max(1);
+        let position = (self.clock.steps(elapsed, params.interval(150, 5)) % span as u64) as usize;
+        let eye = if position < len {
+            position
+        } else {
+            span - position
+        };
+
+        for (i, px) in pixels.iter_mut().enumerate() {
+            let distance = i.abs_diff(eye);
+            *px = if distance < width {
+                scale(
+                    spread_color(params, i, len),
+                    (255 - distance * 255 / width) as u8,
+                )
+            } else {
+                RGB::default()
+            };
+        }
+    }
+}
+
+/// Maps a temperature to black body colours from black through red and yellow to white.
+fn heat_color(heat: u8) -> RGB {
+    let t192 = (u16::from(heat) * 191 / 255) as u8;
+    let ramp = (t192 & 0x3F) << 2;
+
+    if t192 & 0x80 != 0 {
+        RGB {
+            r: 255,
+            g: 255,
+            b: ramp,
+        }
+    } else if t192 & 0x40 != 0 {
+        RGB {
+            r: 255,
+            g: ramp,
+            b: 0,
+        }
+    } else {
+        RGB {
+            r: ramp,
+            g: 0,
+            b: 0,
+        }
+    }
+}
+
+/// A heat simulation rising from the start of the strip, coloured by the palette when one is
+/// selected. Intensity sets how often new sparks ignite, the colour is unused.
+pub struct Fire2012 {
+    clock: Clock,
+    heat: [u8; MAX_PIXELS],
+}
+
+impl Default for Fire2012 {
+    fn default() -> Self {
+        Self {
+            clock: Clock::default(),
+            heat: [0; MAX_PIXELS],
+        }
+    }
+}
+
+impl Animation for Fire2012 {
+    fn init(&mut self, _len: usize, _params: &EffectParams) {
+        self.heat.fill(0);
+    }
+
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        const COOLING: usize = 55;
+
+        let heat = &mut self.heat[..pixels.len()];
+        let len = heat.len();
+        if len == 0 {
+            return;
+        }
+
+        let mut rng = board::Rng;
+        for _ in 0..self.clock.due(elapsed, params.interval(80, 10), 10) {
+            // Every cell cools a little.
+            let max_cooling = (COOLING * 10 / len + 2).min(255) as u8;
+            for cell in heat.iter_mut() {
+                *cell = cell.saturating_sub(rng.gen_range(0..=max_cooling));
+            }
+
+            // Heat drifts up and diffuses.
+            for k in (2..len).rev() {
+                heat[k] = ((u16::from(heat[k - 1]) + u16::from(heat[k - 2]) * 2) / 3) as u8;
+            }
+
+            // New sparks ignite near the bottom.
+            if rng.gen::<u8>() < params.intensity {
+                let y = rng.gen_range(0..len.min(7));
+                heat[y] = heat[y].saturating_add(rng.gen_range(160..=255));
+            }
+        }
+
+        for (px, cell) in pixels.iter_mut().zip(heat.iter()) {
+            *px = params.palette_color(*cell, || heat_color(*cell));
+        }
+    }
+}
+
+/// A meteor falling along the strip leaving a randomly decaying trail, changing colour along the
+/// palette as it falls when one is selected. Intensity sets how quickly the trail decays.
+#[derive(Default)]
+pub struct MeteorRain {
+    clock: Clock,
+    position: usize,
+}
+
+impl Animation for MeteorRain {
+    fn init(&mut self, _len: usize, _params: &EffectParams) {
+        self.position = 0;
+    }
+
+    fn render(&mut self, pixels: &mut [RGB], elapsed: Duration, params: &EffectParams) {
+        const SIZE: usize = 5;
+
+        let len = pixels.len();
+        if len == 0 {
+            return;
+        }
+
+        let mut rng = board::Rng;
+        let decay = params.intensity / 2;
+        for _ in 0..self.clock.due(elapsed, params.interval(150, 5), len as u64) {
+            for px in pixels.iter_mut() {
+                if rng.gen_bool(0.5) {
+                    *px = scale(*px, 255 - decay);
+                }
+            }
+
+            for i in self.position.saturating_sub(SIZE - 1)..=self.position {
+                if let Some(px) = pixels.get_mut(i) {
+                    *px = spread_color(params, i, len);
+                }
+            }
+
+            // Run past the end so the trail can fade out before the next meteor.
+            self.position = (self.position + 1) % (len * 2);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{boxed::Box, vec, vec::Vec};
+
+    use super::*;
+    use crate::leds::{
+        color::{GradientStop, Interpolation, Palette},
+        effects::EffectTuning,
+    };
+
+    const COLOR: RGB = RGB {
+        r: 255,
+        g: 128,
+        b: 0,
+    };
+
+    fn all() -> Vec<Box<dyn Animation>> {
+        vec![
+            Box::new(Flames::default()),
+            Box::new(Rainbow::default()),
+            Box::new(ColorWipe::default()),
+            Box::new(TheaterChase::default()),
+            Box::new(Breathe::default()),
+            Box::new(Twinkle::default()),
+            Box::new(Comet::default()),
+            Box::new(LarsonScanner::default()),
+            Box::new(Fire2012::default()),
+            Box::new(MeteorRain::default()),
+        ]
+    }
+
+    /// The animations that don't use random numbers.
+    fn deterministic() -> Vec<Box<dyn Animation>> {
+        vec![
+            Box::new(Rainbow::default()),
+            Box::new(ColorWipe::default()),
+            Box::new(TheaterChase::default()),
+            Box::new(Breathe::default()),
+            Box::new(Comet::default()),
+            Box::new(LarsonScanner::default()),
+        ]
+    }
+
+    fn params(tuning: EffectTuning) -> EffectParams {
+        EffectParams::new(COLOR, tuning)
+    }
+
+    /// Renders two seconds of frames and then a frame after a long stall, returning every frame.
+    fn run(animation: &mut dyn Animation, len: usize, params: &EffectParams) -> Vec<Vec<RGB>> {
+        let mut pixels = vec![RGB::default(); len];
+        let mut frames = Vec::new();
+        animation.init(len, params);
+
+        let times = (0..400).map(|i| i * 5).chain([3_600_000]);
+        for ms in times {
+            animation.render(&mut pixels, Duration::from_millis(ms), params);
+            frames.push(pixels.clone());
+        }
+        frames
+    }
+
+    #[test]
+    fn render_any_length() {
+        for tuning in [
+            EffectTuning::DEFAULT,
+            EffectTuning {
+                speed: 255,
+                intensity: 255,
+                palette: None,
+            },
+            EffectTuning {
+                speed: 0,
+                intensity: 0,
+                palette: None,
+            },
+        ] {
+            for len in [0, 1, MAX_PIXELS] {
+                for mut animation in all() {
+                    let frames = run(animation.as_mut(), len, &params(tuning));
+                    assert!(frames.iter().all(|frame| frame.len() == len));
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn deterministic_animations_repeat() {
+        let params = params(EffectTuning::DEFAULT);
+        for len in [0, 1, MAX_PIXELS] {
+            for (mut first, mut second) in deterministic().into_iter().zip(deterministic()) {
+                let first = run(first.as_mut(), len, &params);
+                let second = run(second.as_mut(), len, &params);
+                assert!(first == second);
+            }
+        }
+    }
+
+    #[test]
+    fn color_wipe_fills_then_clears() {
+        let params = params(EffectTuning::DEFAULT);
+        let mut wipe = ColorWipe::default();
+        let mut pixels = [RGB::default(); 4];
+        let step = params.interval(200, 5);
+
+        wipe.render(&mut pixels, Duration::from_millis(0), &params);
+        assert!(pixels == [COLOR, RGB::default(), RGB::default(), RGB::default()]);
+
+        wipe.render(&mut pixels, Duration::from_millis(step * 3), &params);
+        assert!(pixels == [COLOR; 4]);
+
+        wipe.render(&mut pixels, Duration::from_millis(step * 5), &params);
+        assert!(pixels == [RGB::default(), RGB::default(), COLOR, COLOR]);
+    }
+
+    #[test]
+    fn every_animation_uses_the_palette() {
+        let blue = RGB { r: 0, g: 0, b: 255 };
+        let palette = Palette::custom(
+            &[GradientStop {
+                position: 0,
+                color: blue,
+            }],
+            Interpolation::Rgb,
+        );
+        let params = params(EffectTuning {
+            palette,
+            ..EffectTuning::DEFAULT
+        });
+
+        for mut animation in all() {
+            let frames = run(animation.as_mut(), MAX_PIXELS, &params);
+            assert!(frames.iter().flatten().all(|px| px.r == 0 && px.g == 0));
+            assert!(frames.iter().flatten().any(|px| px.b > 0));
+        }
+    }
+
+    #[test]
+    fn color_wipe_spreads_the_palette() {
+        let palette = Palette::custom(
+            &[
+                GradientStop {
+                    position: 0,
+                    color: RGB { r: 255, g: 0, b: 0 },
+                },
+                GradientStop {
+                    position: 255,
+                    color: RGB { r: 0, g: 0, b: 255 },
+                },
+            ],
+            Interpolation::Rgb,
+        );
+        let params = params(EffectTuning {
+            palette,
+            ..EffectTuning::DEFAULT
+        });
+        let mut pixels = [RGB::default(); 4];
+
+        ColorWipe::default().render(
+            &mut pixels,
+            Duration::from_millis(params.interval(200, 5) * 3),
+            &params,
+        );
+        assert!(pixels[0] == RGB { r: 255, g: 0, b: 0 });
+        assert!(pixels[3] == RGB { r: 0, g: 0, b: 255 });
+        assert!(pixels[1].r > pixels[2].r && pixels[1].b < pixels[2].b);
+    }
+
+    #[test]
+    fn flames_stay_red_without_intensity() {
+        let params = params(EffectTuning {
+            intensity: 0,
+            ..EffectTuning::DEFAULT
+        });
+        let mut pixels = [RGB::default(); MAX_PIXELS];
+        Flames::default().render(&mut pixels, Duration::from_millis(1000), &params);
+
+        assert!(pixels.iter().all(|px| px.g == 0 && px.b == 0));
+    }
+
+    #[test]
+    fn flames_redraw_every_frame_by_default() {
+        let params = params(EffectTuning::DEFAULT);
+        let mut flames = Flames::default();
+        let mut pixels = [RGB::default(); MAX_PIXELS];
+
+        for frame in 1..=100 {
+            pixels.fill(RGB::default());
+            flames.render(&mut pixels, Duration::from_millis(frame * 5), &params);
+            assert!(pixels.iter().any(|px| *px != RGB::default()));
+        }
+    }
 
-        if ticker.next().await {
-            break;
+    #[test]
+    fn fire_stays_dark_without_sparks() {
+        let params = params(EffectTuning {
+            intensity: 0,
+            ..EffectTuning::DEFAULT
+        });
+        for len in [1, MAX_PIXELS] {
+            let frames = run(&mut Fire2012::default(), len, &params);
+            assert!(frames.iter().flatten().all(|px| *px == RGB::default()));
         }
     }
 }
@@ -0,0 +1,121 @@
+//! Blending between frames, used to cross-fade from one program to the next and to apply
+//! brightness.
+
+use crate::leds::RGB;
+
+/// How far through a transition lasting `duration_ms` we are after `elapsed_ms`, from 0 (just
+/// started) to 255 (complete).
+pub fn progress(elapsed_ms: u64, duration_ms: u64) -> u8 {
+    if elapsed_ms >= duration_ms {
+        255
+    } else {
+        (elapsed_ms * 255 / duration_ms) as u8
+    }
+}
+
+/// Linearly interpolates a single channel, rounding to the nearest value.
+fn mix(from: u8, to: u8, amount: u8) -> u8 {
+    let amount = u16::from(amount);
+    ((u16::from(from) * (255 - amount) + u16::from(to) * amount + 127) / 255) as u8
+}
+
+/// Blends two pixels, an `amount` of 0 gives `from` and 255 gives `to`.
+pub fn blend(from: RGB, to: RGB, amount: u8) -> RGB {
+    RGB {
+        r: mix(from.r, to.r, amount),
+        g: mix(from.g, to.g, amount),
+        b: mix(from.b, to.b, amount),
+    }
+}
+
+/// Scales a pixel by a brightness level, 255 leaves it unchanged.
+pub fn scale(pixel: RGB, level: u8) -> RGB {
+    blend(RGB::default(), pixel, level)
+}
+
+/// Blends two frames into `out`, which determines the number of pixels written. Pixels missing
+/// from either frame are treated as off so strips can fade between different lengths.
+pub fn blend_frame(from: &[RGB], to: &[RGB], out: &mut [RGB], amount: u8) {
+    for (i, pixel) in out.iter_mut().enumerate() {
+        let from = from.get(i).copied().unwrap_or_default();
+        let to = to.get(i).copied().unwrap_or_default();
+        *pixel = blend(from, to, amount);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const RED: RGB = RGB { r: 255, g: 0, b: 0 };
+    const BLUE: RGB = RGB { r: 0, g: 0, b: 255 };
+    const OFF: RGB = RGB { r: 0, g: 0, b: 0 };
+
+    #[test]
+    fn progress_runs_from_start_to_end() {
+        assert_eq!(progress(0, 1000), 0);
+        assert_eq!(progress(500, 1000), 127);
+        assert_eq!(progress(1000, 1000), 255);
+        assert_eq!(progress(5000, 1000), 255);
+    }
+
+    #[test]
+    fn zero_duration_is_complete() {
+        assert_eq!(progress(0, 0), 255);
+    }
+
+    #[test]
+    fn mix_hits_its_endpoints() {
+        for (from, to) in [(0, 255), (255, 0), (17, 200), (90, 90)] {
+            assert_eq!(mix(from, to, 0), from);
+            assert_eq!(mix(from, to, 255), to);
+        }
+        assert_eq!(mix(0, 255, 128), 128);
+    }
+
+    #[test]
+    fn blend_hits_its_endpoints() {
+        assert!(blend(RED, BLUE, 0) == RED);
+        assert!(blend(RED, BLUE, 255) == BLUE);
+        assert!(
+            blend(RED, BLUE, 128)
+                == RGB {
+                    r: 127,
+                    g: 0,
+                    b: 128
+                }
+        );
+    }
+
+    #[test]
+    fn scale_dims_to_off() {
+        assert!(scale(RED, 255) == RED);
+        assert!(scale(RED, 0) == OFF);
+    }
+
+    #[test]
+    fn blend_frame_pads_shorter_frames_with_off() {
+        let from = [RED; 2];
+        let to = [BLUE; 4];
+        let mut out = [RED; 3];
+
+        blend_frame(&from, &to, &mut out, 0);
+        assert!(out == [RED, RED, OFF]);
+
+        blend_frame(&from, &to, &mut out, 255);
+        assert!(out == [BLUE; 3]);
+
+        blend_frame(&to, &from, &mut out, 255);
+        assert!(out == [RED, RED, OFF]);
+    }
+
+    #[test]
+    fn blend_frame_writes_only_the_output() {
+        let mut out = [];
+        blend_frame(&[RED], &[BLUE], &mut out, 128);
+
+        let mut out = [OFF; 1];
+        blend_frame(&[], &[], &mut out, 128);
+        assert!(out == [OFF]);
+    }
+}
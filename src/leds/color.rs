@@ -1,4 +1,7 @@
 use num_traits::float::FloatCore;
+use serde::{Deserialize, Serialize, Serializer};
+
+use crate::leds::blend::blend;
 
 const GAMMA8: [u8; 256] = [
     0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1, 1, 1, 1,
@@ -14,41 +17,78 @@ const GAMMA8: [u8; 256] = [
     223, 225, 228, 231, 233, 236, 239, 241, 244, 247, 249, 252, 255,
 ];
 
-pub trait Order {
-    fn ordered<P: Pixel>(pixel: &P) -> (u8, u8, u8);
+/// Corrects a channel intensity for the eye's response, this is the value actually sent to the
+/// strip.
+pub fn gamma(value: u8) -> u8 {
+    GAMMA8[value as usize]
 }
 
-pub trait Pixel: Sized {
-    fn to_rgb(&self) -> (u8, u8, u8);
-    fn from_rgb(rgb: (u8, u8, u8)) -> Self;
-    fn to_word<O: Order>(&self) -> u32 {
-        let (a, b, c) = O::ordered(self);
-        (u32::from(GAMMA8[a as usize]) << 24)
-            | (u32::from(GAMMA8[b as usize]) << 16)
-            | (u32::from(GAMMA8[c as usize]) << 8)
-    }
+/// The order a strip expects to receive the colour channels in.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub enum ColorOrder {
+    #[default]
+    #[serde(rename = "RGB")]
+    Rgb,
+    #[serde(rename = "RBG")]
+    Rbg,
+    #[serde(rename = "GRB")]
+    Grb,
+    #[serde(rename = "GBR")]
+    Gbr,
+    #[serde(rename = "BRG")]
+    Brg,
+    #[serde(rename = "BGR")]
+    Bgr,
 }
 
-#[allow(clippy::upper_case_acronyms)]
-pub struct OrderGRB;
-impl Order for OrderGRB {
-    fn ordered<P: Pixel>(pixel: &P) -> (u8, u8, u8) {
+impl ColorOrder {
+    pub const ALL: [ColorOrder; 6] = [
+        ColorOrder::Rgb,
+        ColorOrder::Rbg,
+        ColorOrder::Grb,
+        ColorOrder::Gbr,
+        ColorOrder::Brg,
+        ColorOrder::Bgr,
+    ];
+
+    pub fn ordered<P: Pixel>(&self, pixel: &P) -> (u8, u8, u8) {
         let (r, g, b) = pixel.to_rgb();
-        (g, r, b)
+        match self {
+            Self::Rgb => (r, g, b),
+            Self::Rbg => (r, b, g),
+            Self::Grb => (g, r, b),
+            Self::Gbr => (g, b, r),
+            Self::Brg => (b, r, g),
+            Self::Bgr => (b, g, r),
+        }
+    }
+
+    pub fn to_u8(self) -> u8 {
+        self as u8
+    }
+
+    pub fn from_u8(value: u8) -> Option<Self> {
+        Self::ALL.get(value as usize).copied()
     }
 }
 
-#[allow(clippy::upper_case_acronyms)]
-pub struct OrderRGB;
-impl Order for OrderRGB {
-    fn ordered<P: Pixel>(pixel: &P) -> (u8, u8, u8) {
-        let (r, g, b) = pixel.to_rgb();
-        (r, g, b)
+pub trait Pixel: Sized {
+    fn to_rgb(&self) -> (u8, u8, u8);
+    fn from_rgb(rgb: (u8, u8, u8)) -> Self;
+    fn to_word(&self, order: ColorOrder) -> u32 {
+        let (a, b, c) = order.ordered(self);
+        (u32::from(gamma(a)) << 24) | (u32::from(gamma(b)) << 16) | (u32::from(gamma(c)) << 8)
+    }
+
+    /// Packs the colour for a 32-bit RGBW strip, the white channel is always sent last.
+    fn to_rgbw_word(&self, order: ColorOrder, white: WhiteChannel) -> u32 {
+        let rgbw = RGBW::from_rgb(self.to_rgb(), white);
+        RGB::from(rgbw).to_word(order) | u32::from(gamma(rgbw.w))
     }
 }
 
 #[allow(clippy::upper_case_acronyms)]
-#[derive(Clone, Copy, Default)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
 // All components range 0..=255
 pub struct RGB {
     pub r: u8,
@@ -66,6 +106,151 @@ impl Pixel for RGB {
     }
 }
 
+/// The colour of a strip's white LED, used to decide how much of a colour it can display.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum WhiteChannel {
+    /// Takes the component common to all three channels.
+    Neutral,
+    /// A white LED with the given colour temperature in Kelvin.
+    Kelvin(u16),
+}
+
+impl WhiteChannel {
+    /// 0 selects `Neutral`.
+    pub fn from_kelvin(kelvin: u16) -> Self {
+        match kelvin {
+            0 => Self::Neutral,
+            kelvin => Self::Kelvin(kelvin),
+        }
+    }
+
+    pub fn kelvin(&self) -> u16 {
+        match self {
+            Self::Neutral => 0,
+            Self::Kelvin(kelvin) => *kelvin,
+        }
+    }
+
+    /// The colour that full intensity on the white LED is equivalent to.
+    pub fn color(&self) -> RGB {
+        match self {
+            Self::Neutral => RGB {
+                r: 255,
+                g: 255,
+                b: 255,
+            },
+            Self::Kelvin(kelvin) => kelvin_to_rgb(*kelvin),
+        }
+    }
+}
+
+#[allow(clippy::upper_case_acronyms)]
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+// All components range 0..=255
+pub struct RGBW {
+    pub r: u8,
+    pub g: u8,
+    pub b: u8,
+    pub w: u8,
+}
+
+impl RGBW {
+    /// Moves as much of the colour as possible onto the white LED.
+    pub fn from_rgb((r, g, b): (u8, u8, u8), white: WhiteChannel) -> Self {
+        let color = white.color();
+
+        // The most the white LED can display without exceeding any channel.
+        let w = [(r, color.r), (g, color.g), (b, color.b)]
+            .into_iter()
+            .filter(|(_, white)| *white > 0)
+            .map(|(value, white)| u16::from(value) * 255 / u16::from(white))
+            .min()
+            .unwrap_or(0)
+            .min(255);
+
+        let remove =
+            |value: u8, white: u8| value.saturating_sub((w * u16::from(white) / 255) as u8);
+
+        Self {
+            r: remove(r, color.r),
+            g: remove(g, color.g),
+            b: remove(b, color.b),
+            w: w as u8,
+        }
+    }
+}
+
+impl From<RGBW> for RGB {
+    fn from(rgbw: RGBW) -> Self {
+        RGB {
+            r: rgbw.r,
+            g: rgbw.g,
+            b: rgbw.b,
+        }
+    }
+}
+
+/// Adds a level of the white LED back on to a colour, used for commands that set the white
+/// channel directly.
+pub fn add_white(rgb: RGB, w: u8, white: WhiteChannel) -> RGB {
+    let color = white.color();
+    let add =
+        |value: u8, white: u8| value.saturating_add((u16::from(w) * u16::from(white) / 255) as u8);
+
+    RGB {
+        r: add(rgb.r, color.r),
+        g: add(rgb.g, color.g),
+        b: add(rgb.b, color.b),
+    }
+}
+
+const KELVIN_MIN: u16 = 1000;
+const KELVIN_MAX: u16 = 12000;
+const KELVIN_STEP: u16 = 500;
+
+/// The colour of a black body from `KELVIN_MIN` to `KELVIN_MAX` every `KELVIN_STEP`.
+const KELVIN_TABLE: [(u8, u8, u8); 23] = [
+    (255, 68, 0),
+    (255, 108, 0),
+    (255, 137, 14),
+    (255, 159, 70),
+    (255, 177, 110),
+    (255, 193, 141),
+    (255, 206, 166),
+    (255, 218, 187),
+    (255, 228, 206),
+    (255, 237, 222),
+    (255, 246, 237),
+    (255, 254, 250),
+    (243, 242, 255),
+    (230, 235, 255),
+    (221, 230, 255),
+    (215, 226, 255),
+    (210, 223, 255),
+    (205, 220, 255),
+    (202, 218, 255),
+    (199, 216, 255),
+    (196, 214, 255),
+    (193, 213, 255),
+    (191, 211, 255),
+];
+
+/// The colour of white light at a colour temperature, interpolated from a table.
+pub fn kelvin_to_rgb(kelvin: u16) -> RGB {
+    let kelvin = kelvin.clamp(KELVIN_MIN, KELVIN_MAX) - KELVIN_MIN;
+    let index = (kelvin / KELVIN_STEP) as usize;
+    let amount = ((kelvin % KELVIN_STEP) as u32 * 255 / KELVIN_STEP as u32) as u8;
+
+    let from = RGB::from_rgb(KELVIN_TABLE[index]);
+    let to = RGB::from_rgb(KELVIN_TABLE[(index + 1).min(KELVIN_TABLE.len() - 1)]);
+    blend(from, to, amount)
+}
+
+/// Converts between mireds, as used by Home Assistant, and Kelvin.
+pub fn mireds_to_kelvin(mireds: u16) -> u16 {
+    (1_000_000 / u32::from(mireds.max(1))).min(u16::MAX.into()) as u16
+}
+
 pub type Float = f32;
 const ONE_THIRD: Float = 1.0 / 3.0;
 const TWO_THIRD: Float = 2.0 * ONE_THIRD;
@@ -119,10 +304,6 @@ impl Pixel for HSL {
     }
 
     fn to_rgb(&self) -> (u8, u8, u8) {
-        if self.s == 0.0 {
-            return (0, 0, 0);
-        }
-
         fn hue2rgb(p: Float, q: Float, mut t: Float) -> Float {
             if t < 0.0 {
                 t += 1.0
@@ -197,3 +378,432 @@ impl Pixel for HSV {
         HSL { h: self.h, s, l }.to_rgb()
     }
 }
+
+/// Converts a Home Assistant hue (0..360) and saturation (0..100) to a fully bright colour.
+pub fn hs_to_rgb(hue: Float, saturation: Float) -> RGB {
+    RGB::from_rgb(
+        HSV {
+            h: (hue / 360.0).clamp(0.0, 1.0),
+            s: (saturation / 100.0).clamp(0.0, 1.0),
+            v: 1.0,
+        }
+        .to_rgb(),
+    )
+}
+
+/// Converts a CIE 1931 xy chromaticity to the brightest sRGB colour with that chromaticity.
+pub fn xy_to_rgb(x: Float, y: Float) -> RGB {
+    if y <= 0.0 {
+        return RGB::default();
+    }
+
+    // Full luminance, brightness is applied separately.
+    let cx = x / y;
+    let cz = (1.0 - x - y) / y;
+
+    let r = 3.2406 * cx - 1.5372 - 0.4986 * cz;
+    let g = -0.9689 * cx + 1.8758 + 0.0415 * cz;
+    let b = 0.0557 * cx - 0.2040 + 1.0570 * cz;
+
+    let r = r.max(0.0);
+    let g = g.max(0.0);
+    let b = b.max(0.0);
+    let max = r.max(g).max(b);
+    if max == 0.0 {
+        return RGB::default();
+    }
+
+    // Scaled like the hue and saturation colours, the strip applies gamma to both as pixels are
+    // written.
+    let channel = |value: Float| (value / max * 255.0).round() as u8;
+
+    RGB {
+        r: channel(r),
+        g: channel(g),
+        b: channel(b),
+    }
+}
+
+/// The name reported for palettes made from gradient stops.
+pub const CUSTOM_PALETTE: &str = "Custom";
+
+/// The most gradient stops a palette can hold.
+pub const MAX_STOPS: usize = 16;
+
+/// A colour at a position along a palette, from 0 to 255.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct GradientStop {
+    pub position: u8,
+    pub color: RGB,
+}
+
+/// How the colours between two gradient stops are mixed.
+#[derive(Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Interpolation {
+    /// Blends each channel, passing through paler colours between distant hues.
+    #[default]
+    Rgb,
+    /// Moves around the colour wheel the short way, keeping colours saturated.
+    Hsv,
+}
+
+/// Blends two colours through HSV space, an `amount` of 0 gives `from` and 255 gives `to`.
+fn blend_hsv(from: RGB, to: RGB, amount: u8) -> RGB {
+    let from = HSV::from_rgb(from.to_rgb());
+    let to = HSV::from_rgb(to.to_rgb());
+    let t = Float::from(amount) / 255.0;
+
+    let mut delta = to.h - from.h;
+    if delta > 0.5 {
+        delta -= 1.0;
+    } else if delta < -0.5 {
+        delta += 1.0;
+    }
+
+    let mut h = from.h + delta * t;
+    if h < 0.0 {
+        h += 1.0;
+    } else if h >= 1.0 {
+        h -= 1.0;
+    }
+
+    RGB::from_rgb(
+        HSV {
+            h,
+            s: from.s + (to.s - from.s) * t,
+            v: from.v + (to.v - from.v) * t,
+        }
+        .to_rgb(),
+    )
+}
+
+/// The palettes built in to the firmware.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum NamedPalette {
+    Fire,
+    Ocean,
+    Forest,
+    Party,
+    Heat,
+    Rainbow,
+}
+
+const FIRE_STOPS: [(u8, (u8, u8, u8)); 5] = [
+    (0, (0, 0, 0)),
+    (60, (128, 0, 0)),
+    (120, (255, 0, 0)),
+    (180, (255, 128, 0)),
+    (255, (255, 255, 0)),
+];
+
+const OCEAN_STOPS: [(u8, (u8, u8, u8)); 5] = [
+    (0, (0, 0, 32)),
+    (64, (0, 0, 128)),
+    (128, (0, 96, 255)),
+    (192, (0, 192, 192)),
+    (255, (128, 255, 255)),
+];
+
+const FOREST_STOPS: [(u8, (u8, u8, u8)); 5] = [
+    (0, (0, 32, 0)),
+    (80, (0, 100, 0)),
+    (160, (34, 139, 34)),
+    (220, (107, 142, 35)),
+    (255, (144, 238, 144)),
+];
+
+const PARTY_STOPS: [(u8, (u8, u8, u8)); 6] = [
+    (0, (85, 0, 171)),
+    (51, (181, 0, 75)),
+    (102, (255, 85, 0)),
+    (153, (171, 171, 0)),
+    (204, (0, 85, 171)),
+    (255, (85, 0, 171)),
+];
+
+/// Matches the black body colours of the Fire 2012 effect.
+const HEAT_STOPS: [(u8, (u8, u8, u8)); 4] = [
+    (0, (0, 0, 0)),
+    (85, (255, 0, 0)),
+    (170, (255, 255, 0)),
+    (255, (255, 255, 255)),
+];
+
+const RAINBOW_STOPS: [(u8, (u8, u8, u8)); 4] = [
+    (0, (255, 0, 0)),
+    (85, (0, 255, 0)),
+    (170, (0, 0, 255)),
+    (255, (255, 0, 0)),
+];
+
+impl NamedPalette {
+    /// Every built-in palette, in the order they are advertised to Home Assistant.
+    pub const ALL: [NamedPalette; 6] = [
+        NamedPalette::Fire,
+        NamedPalette::Ocean,
+        NamedPalette::Forest,
+        NamedPalette::Party,
+        NamedPalette::Heat,
+        NamedPalette::Rainbow,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Fire => "Fire",
+            Self::Ocean => "Ocean",
+            Self::Forest => "Forest",
+            Self::Party => "Party",
+            Self::Heat => "Heat",
+            Self::Rainbow => "Rainbow",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL
+            .into_iter()
+            .find(|palette| palette.name().eq_ignore_ascii_case(name))
+    }
+
+    pub const fn palette(&self) -> Palette {
+        match self {
+            Self::Fire => Palette::named(*self, &FIRE_STOPS, Interpolation::Rgb),
+            Self::Ocean => Palette::named(*self, &OCEAN_STOPS, Interpolation::Rgb),
+            Self::Forest => Palette::named(*self, &FOREST_STOPS, Interpolation::Rgb),
+            Self::Party => Palette::named(*self, &PARTY_STOPS, Interpolation::Hsv),
+            Self::Heat => Palette::named(*self, &HEAT_STOPS, Interpolation::Rgb),
+            Self::Rainbow => Palette::named(*self, &RAINBOW_STOPS, Interpolation::Hsv),
+        }
+    }
+}
+
+/// A gradient of colours that effects sample by position.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Palette {
+    /// `None` for custom palettes.
+    name: Option<NamedPalette>,
+    /// Sorted by position, only the first `len` are used.
+    stops: [GradientStop; MAX_STOPS],
+    len: u8,
+    pub interpolation: Interpolation,
+}
+
+impl Palette {
+    const fn named(
+        name: NamedPalette,
+        table: &[(u8, (u8, u8, u8))],
+        interpolation: Interpolation,
+    ) -> Self {
+        let mut stops = [GradientStop {
+            position: 0,
+            color: RGB { r: 0, g: 0, b: 0 },
+        }; MAX_STOPS];
+
+        let mut i = 0;
+        while i < table.len() {
+            let (position, (r, g, b)) = table[i];
+            stops[i] = GradientStop {
+                position,
+                color: RGB { r, g, b },
+            };
+            i += 1;
+        }
+
+        Self {
+            name: Some(name),
+            stops,
+            len: table.len() as u8,
+            interpolation,
+        }
+    }
+
+    /// A palette from gradient stops in any order. Returns `None` unless there are between 1 and
+    /// `MAX_STOPS` stops.
+    pub fn custom(stops: &[GradientStop], interpolation: Interpolation) -> Option<Self> {
+        if stops.is_empty() || stops.len() > MAX_STOPS {
+            return None;
+        }
+
+        let mut palette = Self {
+            name: None,
+            stops: [GradientStop::default(); MAX_STOPS],
+            len: stops.len() as u8,
+            interpolation,
+        };
+        palette.stops[..stops.len()].copy_from_slice(stops);
+        palette.stops[..stops.len()].sort_unstable_by_key(|stop| stop.position);
+
+        Some(palette)
+    }
+
+    /// The name of a built-in palette, or `CUSTOM_PALETTE`.
+    pub fn name(&self) -> &'static str {
+        self.name.map(|name| name.name()).unwrap_or(CUSTOM_PALETTE)
+    }
+
+    pub fn stops(&self) -> &[GradientStop] {
+        &self.stops[..self.len as usize]
+    }
+
+    /// The colour at `position` from 0 to 255. Positions before the first stop or after the last
+    /// take that stop's colour.
+    pub fn color_at(&self, position: u8) -> RGB {
+        let stops = self.stops();
+        let next = stops.partition_point(|stop| stop.position <= position);
+
+        if next == 0 {
+            return stops[0].color;
+        }
+        if next == stops.len() {
+            return stops[next - 1].color;
+        }
+
+        let from = stops[next - 1];
+        let to = stops[next];
+        let amount = (u16::from(position - from.position) * 255
+            / u16::from(to.position - from.position)) as u8;
+
+        match self.interpolation {
+            Interpolation::Rgb => blend(from.color, to.color, amount),
+            Interpolation::Hsv => blend_hsv(from.color, to.color, amount),
+        }
+    }
+}
+
+/// Reported by name, the stops of custom palettes aren't echoed back.
+impl Serialize for Palette {
+    fn serialize<S: Serializer>(&self, serializer: S) -> Result<S::Ok, S::Error> {
+        serializer.serialize_str(self.name())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn unsaturated_hs_is_white() {
+        assert!(hs_to_rgb(0.0, 0.0) == RGB::from_rgb((255, 255, 255)));
+        assert!(hs_to_rgb(240.0, 0.0) == RGB::from_rgb((255, 255, 255)));
+    }
+
+    #[test]
+    fn hs_and_xy_agree() {
+        let close = |a: RGB, b: RGB| {
+            a.r.abs_diff(b.r) <= 8 && a.g.abs_diff(b.g) <= 8 && a.b.abs_diff(b.b) <= 8
+        };
+
+        // The sRGB primaries and white point.
+        assert!(close(hs_to_rgb(0.0, 100.0), xy_to_rgb(0.64, 0.33)));
+        assert!(close(hs_to_rgb(120.0, 100.0), xy_to_rgb(0.30, 0.60)));
+        assert!(close(hs_to_rgb(240.0, 100.0), xy_to_rgb(0.15, 0.06)));
+        assert!(close(hs_to_rgb(0.0, 0.0), xy_to_rgb(0.3127, 0.3290)));
+    }
+
+    fn stop(position: u8, (r, g, b): (u8, u8, u8)) -> GradientStop {
+        GradientStop {
+            position,
+            color: RGB { r, g, b },
+        }
+    }
+
+    #[test]
+    fn palette_hits_its_stops() {
+        let fire = NamedPalette::Fire.palette();
+
+        assert!(fire.color_at(0) == RGB::from_rgb((0, 0, 0)));
+        assert!(fire.color_at(120) == RGB::from_rgb((255, 0, 0)));
+        assert!(fire.color_at(255) == RGB::from_rgb((255, 255, 0)));
+    }
+
+    #[test]
+    fn palette_interpolates_between_stops() {
+        let palette = Palette::custom(
+            &[stop(0, (0, 0, 0)), stop(200, (200, 100, 0))],
+            Interpolation::Rgb,
+        )
+        .unwrap();
+
+        let middle = palette.color_at(100);
+        assert!(middle.r.abs_diff(100) <= 1);
+        assert!(middle.g.abs_diff(50) <= 1);
+        assert_eq!(middle.b, 0);
+
+        // Brightness only grows along the gradient.
+        let reds: std::vec::Vec<u8> = (0..=200)
+            .map(|position| palette.color_at(position).r)
+            .collect();
+        assert!(reds.windows(2).all(|pair| pair[0] <= pair[1]));
+    }
+
+    #[test]
+    fn palette_holds_its_outer_stops() {
+        let palette = Palette::custom(
+            &[stop(192, (0, 0, 255)), stop(64, (255, 0, 0))],
+            Interpolation::Rgb,
+        )
+        .unwrap();
+
+        assert!(palette.color_at(0) == RGB::from_rgb((255, 0, 0)));
+        assert!(palette.color_at(64) == RGB::from_rgb((255, 0, 0)));
+        assert!(palette.color_at(192) == RGB::from_rgb((0, 0, 255)));
+        assert!(palette.color_at(255) == RGB::from_rgb((0, 0, 255)));
+
+        let single = Palette::custom(&[stop(128, (1, 2, 3))], Interpolation::Rgb).unwrap();
+        assert!(single.color_at(0) == RGB::from_rgb((1, 2, 3)));
+        assert!(single.color_at(255) == RGB::from_rgb((1, 2, 3)));
+    }
+
+    #[test]
+    fn hsv_palette_takes_the_short_way_around_the_hue_circle() {
+        // From pink at 324° to orange at 36°, passing through red rather than green and blue.
+        let stops = [stop(0, (255, 0, 153)), stop(254, (255, 153, 0))];
+        let hsv = Palette::custom(&stops, Interpolation::Hsv).unwrap();
+        let rgb = Palette::custom(&stops, Interpolation::Rgb).unwrap();
+
+        let middle = hsv.color_at(127);
+        assert_eq!(middle.r, 255);
+        assert!(middle.g <= 8);
+        assert!(middle.b <= 8);
+
+        // Mixing the channels instead washes the colour out.
+        let mixed = rgb.color_at(127);
+        assert!(mixed.g > 64 && mixed.b > 64);
+
+        // Hue keeps full saturation all the way along.
+        for position in 0..=254 {
+            let color = hsv.color_at(position);
+            assert_eq!(color.r.max(color.g).max(color.b), 255);
+            assert!(color.r.min(color.g).min(color.b) <= 1);
+        }
+    }
+
+    #[test]
+    fn named_palettes_resolve() {
+        let names: std::vec::Vec<&str> = NamedPalette::ALL
+            .iter()
+            .map(|palette| palette.name())
+            .collect();
+        assert_eq!(
+            names,
+            ["Fire", "Ocean", "Forest", "Party", "Heat", "Rainbow"]
+        );
+
+        for named in NamedPalette::ALL {
+            assert!(NamedPalette::from_name(named.name()) == Some(named));
+            assert!(NamedPalette::from_name(&named.name().to_ascii_lowercase()) == Some(named));
+
+            let palette = named.palette();
+            assert_eq!(palette.name(), named.name());
+            assert_eq!(palette.stops().first().unwrap().position, 0);
+            assert_eq!(palette.stops().last().unwrap().position, 255);
+            assert!(palette
+                .stops()
+                .windows(2)
+                .all(|pair| pair[0].position < pair[1].position));
+        }
+
+        assert!(NamedPalette::from_name(CUSTOM_PALETTE).is_none());
+        assert!(NamedPalette::from_name("Lava").is_none());
+    }
+}
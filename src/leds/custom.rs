@@ -0,0 +1,245 @@
+//! Pixels set individually over MQTT, shown by `LedProgram::Custom`.
+//!
+//! Changes are sent either as JSON or as a binary payload. The JSON form is an object with any of:
+//!
+//! * `fill` - a colour as `[red, green, blue]` given to every pixel before anything else applies.
+//! * `start` - the pixel that `pixels` and `hex` start at, defaults to 0.
+//! * `pixels` - a list of colours as `[red, green, blue]`.
+//! * `hex` - colours as consecutive `rrggbb` hex digits.
+//! * `ranges` - a list of `{"start": 10, "end": 20, "color": [255, 0, 0]}` that give every pixel
+//!   from `start` to `end` inclusive a colour.
+//!
+//! The binary form is the pixel to start at as a big endian `u16` followed by the colours as
+//! consecutive red, green and blue bytes.
+
+use core::cell::RefCell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use heapless::Vec;
+use serde::Deserialize;
+
+use crate::leds::{MAX_PIXELS, MAX_SEGMENTS, RGB};
+
+/// The most ranges a single JSON change can set.
+const MAX_RANGES: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PixelsError {
+    /// The payload is neither valid JSON nor a binary change.
+    Decode,
+    /// The `hex` field holds something other than whole `rrggbb` colours.
+    Hex,
+    /// A range ends before it starts.
+    Range,
+}
+
+#[derive(Deserialize)]
+struct PixelRange {
+    start: u16,
+    /// Inclusive.
+    end: u16,
+    color: [u8; 3],
+}
+
+#[derive(Deserialize)]
+struct PixelsCommand<'a> {
+    fill: Option<[u8; 3]>,
+    start: Option<u16>,
+    pixels: Option<Vec<[u8; 3], MAX_PIXELS>>,
+    #[serde(borrow)]
+    hex: Option<&'a str>,
+    ranges: Option<Vec<PixelRange, MAX_RANGES>>,
+}
+
+fn rgb([r, g, b]: [u8; 3]) -> RGB {
+    RGB { r, g, b }
+}
+
+/// Applies a JSON or binary change to `pixels`, colours beyond its end are ignored. Nothing is
+/// changed when the payload is invalid.
+pub fn apply_pixels(payload: &[u8], pixels: &mut [RGB]) -> Result<(), PixelsError> {
+    if payload.first() != Some(&b'{') {
+        let [high, low, colors @ ..] = payload else {
+            return Err(PixelsError::Decode);
+        };
+        let start = usize::from(u16::from_be_bytes([*high, *low]));
+
+        for (pixel, color) in pixels.iter_mut().skip(start).zip(colors.chunks_exact(3)) {
+            *pixel = rgb([color[0], color[1], color[2]]);
+        }
+        return Ok(());
+    }
+
+    let (command, _) =
+        serde_json_core::from_slice::<PixelsCommand>(payload).map_err(|_| PixelsError::Decode)?;
+
+    let hex = command.hex.unwrap_or_default().as_bytes();
+    if hex.len() % 6 != 0 || !hex.iter().all(u8::is_ascii_hexdigit) {
+        return Err(PixelsError::Hex);
+    }
+    let ranges = command.ranges.unwrap_or_default();
+    if ranges.iter().any(|range| range.end < range.start) {
+        return Err(PixelsError::Range);
+    }
+
+    if let Some(color) = command.fill {
+        pixels.fill(rgb(color));
+    }
+
+    let start = usize::from(command.start.unwrap_or(0));
+    if let Some(colors) = command.pixels {
+        for (pixel, color) in pixels.iter_mut().skip(start).zip(colors) {
+            *pixel = rgb(color);
+        }
+    }
+
+    for (pixel, digits) in pixels.iter_mut().skip(start).zip(hex.chunks_exact(6)) {
+        let mut color = [0; 3];
+        hex::decode_to_slice(digits, &mut color).map_err(|_| PixelsError::Hex)?;
+        *pixel = rgb(color);
+    }
+
+    for range in ranges {
+        let start = usize::from(range.start).min(pixels.len());
+        let end = (usize::from(range.end) + 1).min(pixels.len());
+        pixels[start..end].fill(rgb(range.color));
+    }
+
+    Ok(())
+}
+
+type Pixels = Mutex<CriticalSectionRawMutex, RefCell<[RGB; MAX_PIXELS]>>;
+
+/// The pixels of each segment's custom program.
+static CUSTOM_PIXELS: [Pixels; MAX_SEGMENTS] = [
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+];
+
+/// Applies a change to a segment's custom pixels. The custom program must be restarted to show
+/// it.
+pub fn update_custom_pixels(segment: usize, payload: &[u8]) -> Result<(), PixelsError> {
+    CUSTOM_PIXELS[segment].lock(|pixels| apply_pixels(payload, &mut *pixels.borrow_mut()))
+}
+
+/// Copies the start of a segment's custom pixels into `pixels`.
+pub fn read_custom_pixels(segment: usize, pixels: &mut [RGB]) {
+    CUSTOM_PIXELS[segment].lock(|custom| {
+        pixels.copy_from_slice(&custom.borrow()[..pixels.len()]);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const OFF: RGB = RGB { r: 0, g: 0, b: 0 };
+    const RED: RGB = RGB { r: 255, g: 0, b: 0 };
+    const BLUE: RGB = RGB { r: 0, g: 0, b: 255 };
+
+    #[test]
+    fn fills_before_everything_else() {
+        let mut pixels = [OFF; 4];
+        apply_pixels(br#"{"pixels":[[0,0,255]],"fill":[255,0,0]}"#, &mut pixels).unwrap();
+
+        assert!(pixels == [BLUE, RED, RED, RED]);
+    }
+
+    #[test]
+    fn sets_pixels_from_start() {
+        let mut pixels = [OFF; 4];
+        apply_pixels(
+            br#"{"start":2,"pixels":[[255,0,0],[0,0,255],[0,0,255]]}"#,
+            &mut pixels,
+        )
+        .unwrap();
+
+        assert!(pixels == [OFF, OFF, RED, BLUE]);
+    }
+
+    #[test]
+    fn sets_ranges_inclusively() {
+        let mut pixels = [OFF; 6];
+        let payload = br#"{"ranges":[{"start":1,"end":2,"color":[255,0,0]},{"start":4,"end":400,"color":[0,0,255]}]}"#;
+        apply_pixels(payload, &mut pixels).unwrap();
+
+        assert!(pixels == [OFF, RED, RED, OFF, BLUE, BLUE]);
+    }
+
+    #[test]
+    fn rejects_backwards_ranges() {
+        let mut pixels = [OFF; 4];
+        let payload = br#"{"fill":[255,0,0],"ranges":[{"start":2,"end":1,"color":[0,0,255]}]}"#;
+
+        assert_eq!(apply_pixels(payload, &mut pixels), Err(PixelsError::Range));
+        assert!(pixels == [OFF; 4]);
+    }
+
+    #[test]
+    fn sets_hex_colours() {
+        let mut pixels = [OFF; 3];
+        apply_pixels(br#"{"start":1,"hex":"ff00000000FF"}"#, &mut pixels).unwrap();
+
+        assert!(pixels == [OFF, RED, BLUE]);
+    }
+
+    #[test]
+    fn rejects_partial_and_invalid_hex() {
+        let mut pixels = [OFF; 3];
+
+        assert_eq!(
+            apply_pixels(br#"{"fill":[255,0,0],"hex":"ff00"}"#, &mut pixels),
+            Err(PixelsError::Hex)
+        );
+        assert_eq!(
+            apply_pixels(br#"{"hex":"ff000g"}"#, &mut pixels),
+            Err(PixelsError::Hex)
+        );
+        assert!(pixels == [OFF; 3]);
+    }
+
+    #[test]
+    fn sets_binary_colours() {
+        let mut pixels = [OFF; 4];
+        apply_pixels(&[0, 1, 255, 0, 0, 0, 0, 255, 7], &mut pixels).unwrap();
+
+        // The incomplete colour at the end is ignored.
+        assert!(pixels == [OFF, RED, BLUE, OFF]);
+    }
+
+    #[test]
+    fn rejects_short_and_invalid_payloads() {
+        let mut pixels = [OFF; 4];
+
+        assert_eq!(apply_pixels(&[0], &mut pixels), Err(PixelsError::Decode));
+        assert_eq!(
+            apply_pixels(b"{\"fill\":", &mut pixels),
+            Err(PixelsError::Decode)
+        );
+        assert!(pixels == [OFF; 4]);
+    }
+
+    #[test]
+    fn ignores_starts_past_the_end() {
+        let mut pixels = [OFF; 4];
+
+        apply_pixels(&[0xFF, 0xFF, 255, 0, 0], &mut pixels).unwrap();
+        apply_pixels(
+            br#"{"start":4,"pixels":[[255,0,0]],"hex":"ff0000"}"#,
+            &mut pixels,
+        )
+        .unwrap();
+        apply_pixels(
+            br#"{"ranges":[{"start":65535,"end":65535,"color":[255,0,0]}]}"#,
+            &mut pixels,
+        )
+        .unwrap();
+        assert!(pixels == [OFF; 4]);
+    }
+}
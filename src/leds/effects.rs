@@ -0,0 +1,178 @@
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use serde::Serialize;
+
+use crate::leds::{
+    color::{NamedPalette, Palette, CUSTOM_PALETTE},
+    MAX_SEGMENTS, RGB,
+};
+
+/// The named effects that can be selected from Home Assistant.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum Effect {
+    Flames,
+    Rainbow,
+    ColorWipe,
+    TheaterChase,
+    Breathe,
+    Twinkle,
+    Comet,
+    LarsonScanner,
+    Fire2012,
+    MeteorRain,
+}
+
+impl Effect {
+    /// Every effect, in the order they are advertised to Home Assistant.
+    pub const ALL: [Effect; 10] = [
+        Effect::Flames,
+        Effect::Rainbow,
+        Effect::ColorWipe,
+        Effect::TheaterChase,
+        Effect::Breathe,
+        Effect::Twinkle,
+        Effect::Comet,
+        Effect::LarsonScanner,
+        Effect::Fire2012,
+        Effect::MeteorRain,
+    ];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::Flames => "Flames",
+            Self::Rainbow => "Rainbow",
+            Self::ColorWipe => "Color Wipe",
+            Self::TheaterChase => "Theater Chase",
+            Self::Breathe => "Breathe",
+            Self::Twinkle => "Twinkle",
+            Self::Comet => "Comet",
+            Self::LarsonScanner => "Larson Scanner",
+            Self::Fire2012 => "Fire 2012",
+            Self::MeteorRain => "Meteor Rain",
+        }
+    }
+
+    pub fn from_name(name: &str) -> Option<Self> {
+        Self::ALL.into_iter().find(|effect| effect.name() == name)
+    }
+}
+
+pub const EFFECT_COUNT: usize = Effect::ALL.len();
+
+/// The effect names advertised in the light's discovery payload.
+pub const EFFECT_NAMES: [&str; EFFECT_COUNT] = {
+    let mut names = [""; EFFECT_COUNT];
+    let mut i = 0;
+    while i < EFFECT_COUNT {
+        names[i] = Effect::ALL[i].name();
+        i += 1;
+    }
+    names
+};
+
+/// Selecting this palette leaves effects to choose their own colours.
+pub const DEFAULT_PALETTE: &str = "Default";
+
+pub const PALETTE_OPTION_COUNT: usize = NamedPalette::ALL.len() + 2;
+
+/// The palette names advertised to Home Assistant, ending with the name reported while a custom
+/// palette is selected.
+pub const PALETTE_OPTIONS: [&str; PALETTE_OPTION_COUNT] = {
+    let mut names = [DEFAULT_PALETTE; PALETTE_OPTION_COUNT];
+    let mut i = 0;
+    while i < NamedPalette::ALL.len() {
+        names[i + 1] = NamedPalette::ALL[i].name();
+        i += 1;
+    }
+    names[PALETTE_OPTION_COUNT - 1] = CUSTOM_PALETTE;
+    names
+};
+
+/// The adjustable parameters of a segment's effects, changes apply to the running effect.
+#[derive(Clone, Copy, PartialEq, Eq, Serialize)]
+pub struct EffectTuning {
+    /// From 0 (slowest) to 255 (fastest).
+    pub speed: u8,
+    /// An effect specific amount, such as the length of a tail or the density of sparkles.
+    pub intensity: u8,
+    /// Colours for effects that can use them, `None` lets each effect use its own.
+    pub palette: Option<Palette>,
+}
+
+impl EffectTuning {
+    pub const DEFAULT: Self = Self {
+        speed: 128,
+        intensity: 128,
+        palette: None,
+    };
+
+    pub fn get(segment: usize) -> Self {
+        EFFECT_TUNING.lock(|tuning| tuning.get()[segment])
+    }
+
+    pub fn set(segment: usize, tuning: EffectTuning) {
+        EFFECT_TUNING.lock(|cell| {
+            let mut all = cell.get();
+            all[segment] = tuning;
+            cell.set(all);
+        });
+    }
+}
+
+static EFFECT_TUNING: Mutex<CriticalSectionRawMutex, Cell<[EffectTuning; MAX_SEGMENTS]>> =
+    Mutex::new(Cell::new([EffectTuning::DEFAULT; MAX_SEGMENTS]));
+
+/// What an effect draws each frame with.
+#[derive(Clone, Copy)]
+pub struct EffectParams {
+    /// The main colour for effects that use one, taken from the colour showing when the effect
+    /// was selected.
+    pub color: RGB,
+    pub speed: u8,
+    pub intensity: u8,
+    pub palette: Option<Palette>,
+}
+
+impl EffectParams {
+    pub fn new(color: RGB, tuning: EffectTuning) -> Self {
+        Self {
+            color,
+            speed: tuning.speed,
+            intensity: tuning.intensity,
+            palette: tuning.palette,
+        }
+    }
+
+    /// The colour at `position` along the selected palette, or the effect's own colour when none
+    /// is selected.
+    pub fn palette_color(&self, position: u8, own: impl FnOnce() -> RGB) -> RGB {
+        match &self.palette {
+            Some(palette) => palette.color_at(position),
+            None => own(),
+        }
+    }
+
+    /// Maps the speed to a time between `slowest_ms` and `fastest_ms`.
+    pub fn interval(&self, slowest_ms: u64, fastest_ms: u64) -> u64 {
+        let range = slowest_ms.saturating_sub(fastest_ms);
+        (slowest_ms - range * u64::from(self.speed) / 255).max(1)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::leds::{color::GradientStop, Interpolation};
+
+    #[test]
+    fn every_palette_name_is_an_option() {
+        let custom = Palette::custom(&[GradientStop::default()], Interpolation::default()).unwrap();
+        let palettes = NamedPalette::ALL.iter().map(|named| named.palette());
+
+        for palette in palettes.chain([custom]) {
+            assert!(PALETTE_OPTIONS.contains(&palette.name()));
+        }
+        assert_eq!(PALETTE_OPTIONS[0], DEFAULT_PALETTE);
+    }
+}
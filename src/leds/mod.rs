@@ -1,99 +1,630 @@
+use core::cell::Cell;
+
+#[cfg(feature = "device")]
 use embassy_executor::Spawner;
+#[cfg(feature = "device")]
+use embassy_futures::join::{join, join4, join_array};
 use embassy_futures::select::{select, Either};
-use embassy_sync::{blocking_mutex::raw::CriticalSectionRawMutex, channel};
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    channel,
+};
+#[cfg(feature = "device")]
+use embassy_time::Timer;
 use embassy_time::{Duration, Ticker};
 use log::info;
-use mcutie::homeassistant::{
-    binary_sensor::BinarySensorState,
-    light::{Color, LightState},
-};
+#[cfg(feature = "device")]
+use log::warn;
+#[cfg(feature = "device")]
+use mcutie::Publishable;
+use portable_atomic::AtomicU32;
+#[cfg(feature = "device")]
+use static_cell::StaticCell;
 
 mod animations;
+pub mod blend;
 mod color;
+mod custom;
+mod effects;
+mod output;
+pub mod power;
+mod realtime;
+mod render;
+mod segment;
+mod state;
+
+pub use animations::Animation;
+pub use color::{
+    add_white, hs_to_rgb, kelvin_to_rgb, mireds_to_kelvin, xy_to_rgb, ColorOrder, GradientStop,
+    Interpolation, NamedPalette, Palette, WhiteChannel, CUSTOM_PALETTE, MAX_STOPS, RGB, RGBW,
+};
+pub use custom::{apply_pixels, update_custom_pixels, PixelsError};
+pub use effects::{
+    Effect, EffectParams, EffectTuning, DEFAULT_PALETTE, EFFECT_COUNT, EFFECT_NAMES,
+    PALETTE_OPTIONS, PALETTE_OPTION_COUNT,
+};
+pub use output::Output;
+pub use power::PowerModel;
+pub use realtime::{
+    is_realtime, realtime_deadline, realtime_source, show_realtime, stop_realtime, write_realtime,
+    RealtimeSource,
+};
+pub use render::RenderStats;
+pub use segment::{Segment, SegmentSettings, SegmentSink};
+pub use state::{ColorState, ReportedState};
+
+use animations::{
+    Breathe, ColorWipe, Comet, Fire2012, Flames, LarsonScanner, MeteorRain, Rainbow, TheaterChase,
+    Twinkle,
+};
+use custom::read_custom_pixels;
+use render::render_loop;
 
+#[cfg(feature = "device")]
 use crate::{
-    board::Ws2812,
-    leds::color::{Order, OrderRGB, Pixel, RGB},
-    LED_ENTITY,
+    app::LED_STATE_TOPICS,
+    board::{Ws2812, Ws2812Outputs},
+    buffer::ByteBuffer,
+    leds::{
+        color::Pixel,
+        power::limit,
+        realtime::read_realtime,
+        segment::{read_frame, FRAME_CHANGED},
+    },
 };
 
-pub static LED_CHANNEL: channel::Channel<CriticalSectionRawMutex, LedProgram, 1> =
-    channel::Channel::new();
+/// The longest strip that can be driven, pixel buffers are sized to hold this many pixels.
+pub const MAX_PIXELS: usize = 300;
 
-#[derive(Clone, Copy)]
+/// The number of strips that can be driven, each from its own state machine of PIO1.
+pub const MAX_OUTPUTS: usize = 4;
+
+/// The number of independently controlled segments the outputs can be divided into.
+pub const MAX_SEGMENTS: usize = 8;
+
+const FRAME_INTERVAL: Duration = Duration::from_millis(5);
+
+pub type LedChannel = channel::Channel<CriticalSectionRawMutex, LedCommand, 1>;
+
+/// Sends programs to each segment.
+pub static LED_CHANNELS: [LedChannel; MAX_SEGMENTS] = [
+    channel::Channel::new(),
+    channel::Channel::new(),
+    channel::Channel::new(),
+    channel::Channel::new(),
+    channel::Channel::new(),
+    channel::Channel::new(),
+    channel::Channel::new(),
+    channel::Channel::new(),
+];
+
+/// The estimated current drawn by the most recent frame of each segment, in milliamps.
+pub static CURRENT_MA: [AtomicU32; MAX_SEGMENTS] = [
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+    AtomicU32::new(0),
+];
+
+const DEFAULT_SETTINGS: StripSettings = StripSettings {
+    pixels: 0,
+    order: ColorOrder::Rgb,
+    white: None,
+    power: PowerModel {
+        ma_per_channel: 20,
+        budget_ma: 0,
+        white: None,
+    },
+};
+
+/// The physical layout of each strip, read each time a frame is written.
+pub static STRIP_SETTINGS: Mutex<CriticalSectionRawMutex, Cell<[StripSettings; MAX_OUTPUTS]>> =
+    Mutex::new(Cell::new([DEFAULT_SETTINGS; MAX_OUTPUTS]));
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct StripSettings {
+    pub pixels: usize,
+    pub order: ColorOrder,
+    /// Set for RGBW strips.
+    pub white: Option<WhiteChannel>,
+    /// The budget that streamed frames are limited to.
+    pub power: PowerModel,
+}
+
+impl StripSettings {
+    pub fn get(output: usize) -> Self {
+        STRIP_SETTINGS.lock(|settings| settings.get()[output])
+    }
+
+    pub fn set(output: usize, settings: StripSettings) {
+        STRIP_SETTINGS.lock(|cell| {
+            let mut all = cell.get();
+            all[output] = StripSettings {
+                pixels: settings.pixels.min(MAX_PIXELS),
+                ..settings
+            };
+            cell.set(all);
+        });
+    }
+}
+
+/// Something that can display frames of pixels, either a physical strip or a simulated one.
+#[allow(async_fn_in_trait)]
+pub trait FrameSink {
+    async fn write(&mut self, pixels: &[RGB]);
+}
+
+impl<S: FrameSink> FrameSink for &mut S {
+    async fn write(&mut self, pixels: &[RGB]) {
+        (**self).write(pixels).await
+    }
+}
+
+/// What the strip should display. Brightness is kept separate from the colour and applied as
+/// frames are output so dimming never loses colour precision.
+#[derive(Clone, Copy, PartialEq)]
 pub enum LedProgram {
     Off,
-    Solid { red: u8, green: u8, blue: u8 },
-    Flames,
+    Solid {
+        red: u8,
+        green: u8,
+        blue: u8,
+        brightness: u8,
+    },
+    /// White light at a colour temperature given in mireds.
+    ColorTemp {
+        mireds: u16,
+        brightness: u8,
+    },
+    /// A colour given as hue (0..360) and saturation (0..100).
+    Hs {
+        hue: f32,
+        saturation: f32,
+        brightness: u8,
+    },
+    /// A colour given as CIE 1931 xy chromaticity.
+    Xy {
+        x: f32,
+        y: f32,
+        brightness: u8,
+    },
+    /// An animation, drawn with the colour and the segment's current `EffectTuning`.
+    Effect {
+        effect: Effect,
+        color: RGB,
+        brightness: u8,
+    },
+    /// Pixels set individually, read from the segment's custom pixels each time it starts.
+    Custom {
+        brightness: u8,
+    },
+}
+
+/// Switches the strip to a new program, fading from the current frame over `transition`.
+#[derive(Clone, Copy)]
+pub struct LedCommand {
+    pub program: LedProgram,
+    pub transition: Duration,
 }
 
+/// Ticks until a new program is sent to a segment's channel.
 struct AbortableTicker {
     ticker: Ticker,
+    channel: &'static LedChannel,
 }
 
 impl AbortableTicker {
-    fn every(duration: Duration) -> Self {
+    fn every(duration: Duration, segment: usize) -> Self {
         Self {
             ticker: Ticker::every(duration),
+            channel: &LED_CHANNELS[segment],
         }
     }
 
     async fn next(&mut self) -> bool {
-        let result = select(self.ticker.next(), LED_CHANNEL.ready_to_receive()).await;
+        let result = select(self.ticker.next(), self.channel.ready_to_receive()).await;
 
         matches!(result, Either::Second(_))
     }
 }
 
 impl LedProgram {
-    async fn run<const N: usize, O: Order>(&self, ws2812: &mut Ws2812) {
-        let ticker = AbortableTicker::every(Duration::from_millis(5));
+    /// The brightness frames are output at, `Off` is always 0.
+    pub fn brightness(&self) -> u8 {
+        match self {
+            Self::Off => 0,
+            Self::Solid { brightness, .. }
+            | Self::ColorTemp { brightness, .. }
+            | Self::Hs { brightness, .. }
+            | Self::Xy { brightness, .. }
+            | Self::Effect { brightness, .. }
+            | Self::Custom { brightness } => *brightness,
+        }
+    }
+
+    pub fn with_brightness(self, brightness: u8) -> Self {
+        match self {
+            Self::Off => Self::Off,
+            Self::Solid {
+                red, green, blue, ..
+            } => Self::Solid {
+                red,
+                green,
+                blue,
+                brightness,
+            },
+            Self::ColorTemp { mireds, .. } => Self::ColorTemp { mireds, brightness },
+            Self::Hs {
+                hue, saturation, ..
+            } => Self::Hs {
+                hue,
+                saturation,
+                brightness,
+            },
+            Self::Xy { x, y, .. } => Self::Xy { x, y, brightness },
+            Self::Effect { effect, color, .. } => Self::Effect {
+                effect,
+                color,
+                brightness,
+            },
+            Self::Custom { .. } => Self::Custom { brightness },
+        }
+    }
 
+    /// The colour this program shows, used as the colour of effects selected while it runs.
+    pub fn color(&self) -> Option<RGB> {
+        match self {
+            Self::Off | Self::Custom { .. } => None,
+            Self::Solid {
+                red, green, blue, ..
+            } => Some(RGB {
+                r: *red,
+                g: *green,
+                b: *blue,
+            }),
+            Self::ColorTemp { mireds, .. } => Some(kelvin_to_rgb(mireds_to_kelvin(*mireds))),
+            Self::Hs {
+                hue, saturation, ..
+            } => Some(hs_to_rgb(*hue, *saturation)),
+            Self::Xy { x, y, .. } => Some(xy_to_rgb(*x, *y)),
+            Self::Effect { color, .. } => Some(*color),
+        }
+    }
+
+    /// The state reported to Home Assistant while this program is running, on RGBW strips the
+    /// colour is reported with its white component extracted.
+    pub fn reported_state(&self, white: Option<WhiteChannel>) -> ReportedState {
+        match self {
+            Self::Off => ReportedState {
+                state: "OFF",
+                brightness: None,
+                color_mode: None,
+                color: None,
+                color_temp: None,
+                effect: None,
+            },
+            Self::Solid {
+                red,
+                green,
+                blue,
+                brightness,
+            } => ReportedState {
+                state: "ON",
+                brightness: Some(*brightness),
+                color_mode: Some(if white.is_some() { "rgbw" } else { "rgb" }),
+                color: Some(match white {
+                    Some(white) => {
+                        let rgbw = RGBW::from_rgb((*red, *green, *blue), white);
+                        ColorState::Rgb {
+                            r: rgbw.r,
+                            g: rgbw.g,
+                            b: rgbw.b,
+                            w: Some(rgbw.w),
+                        }
+                    }
+                    None => ColorState::Rgb {
+                        r: *red,
+                        g: *green,
+                        b: *blue,
+                        w: None,
+                    },
+                }),
+                color_temp: None,
+                effect: None,
+            },
+            Self::ColorTemp { mireds, brightness } => ReportedState {
+                state: "ON",
+                brightness: Some(*brightness),
+                color_mode: Some("color_temp"),
+                color: None,
+                color_temp: Some(*mireds),
+                effect: None,
+            },
+            Self::Hs {
+                hue,
+                saturation,
+                brightness,
+            } => ReportedState {
+                state: "ON",
+                brightness: Some(*brightness),
+                color_mode: Some("hs"),
+                color: Some(ColorState::Hs {
+                    h: *hue,
+                    s: *saturation,
+                }),
+                color_temp: None,
+                effect: None,
+            },
+            Self::Xy { x, y, brightness } => ReportedState {
+                state: "ON",
+                brightness: Some(*brightness),
+                color_mode: Some("xy"),
+                color: Some(ColorState::Xy { x: *x, y: *y }),
+                color_temp: None,
+                effect: None,
+            },
+            Self::Effect {
+                effect, brightness, ..
+            } => ReportedState {
+                state: "ON",
+                brightness: Some(*brightness),
+                color_mode: None,
+                color: None,
+                color_temp: None,
+                effect: Some(effect.name()),
+            },
+            Self::Custom { brightness } => ReportedState {
+                state: "ON",
+                brightness: Some(*brightness),
+                color_mode: None,
+                color: None,
+                color_temp: None,
+                effect: None,
+            },
+        }
+    }
+
+    #[cfg(feature = "device")]
+    pub async fn publish_state(&self, segment: usize) {
+        let mut buffer = ByteBuffer::<256>::new();
+        let output = SegmentSettings::get(segment).segment.output;
+        let white = StripSettings::get(output).white;
+        if buffer.serialize(&self.reported_state(white)).is_err() {
+            warn!("Failed to encode light state");
+            return;
+        }
+
+        let _ = LED_STATE_TOPICS[segment]
+            .with_bytes(buffer.buffer())
+            .publish()
+            .await;
+    }
+
+    /// Runs this program against a segment, drawing into `pixels` which is as long as the
+    /// segment. Static programs return once their frame is written, animations run until a new
+    /// program is sent to `segment`'s channel.
+    pub async fn run<S: FrameSink>(&self, sink: &mut S, pixels: &mut [RGB], segment: usize) {
         match self {
             Self::Off => {
-                info!("OFF");
-                ws2812.write(&[0_u32; N]).await;
-
-                let _ = LED_ENTITY
-                    .publish_state(LightState {
-                        state: BinarySensorState::Off,
-                        color: Color::None,
-                        effect: None,
-                    })
-                    .await;
+                info!("{segment}: OFF");
+                sink.write(pixels).await;
             }
-            Self::Solid { red, green, blue } => {
-                let word = RGB::from_rgb((*red, *green, *blue)).to_word::<O>();
-                info!("ON {word}");
-                ws2812.write(&[word; N]).await;
-
-                let _ = LED_ENTITY
-                    .publish_state(LightState {
-                        state: BinarySensorState::On,
-                        color: Color::Rgb {
-                            red: *red,
-                            green: *green,
-                            blue: *blue,
-                        },
-                        effect: None,
-                    })
-                    .await;
+            Self::Custom { .. } => {
+                info!("{segment}: CUSTOM");
+                read_custom_pixels(segment, pixels);
+                sink.write(pixels).await;
             }
-            Self::Flames => {
-                animations::flames::<N, O>(ticker, ws2812).await;
+            Self::Effect { effect, color, .. } => {
+                info!("{segment}: EFFECT {}", effect.name());
+
+                let color = *color;
+                match effect {
+                    Effect::Flames => {
+                        render_loop(sink, pixels, segment, color, Flames::default()).await
+                    }
+                    Effect::Rainbow => {
+                        render_loop(sink, pixels, segment, color, Rainbow::default()).await
+                    }
+                    Effect::ColorWipe => {
+                        render_loop(sink, pixels, segment, color, ColorWipe::default()).await
+                    }
+                    Effect::TheaterChase => {
+                        render_loop(sink, pixels, segment, color, TheaterChase::default()).await
+                    }
+                    Effect::Breathe => {
+                        render_loop(sink, pixels, segment, color, Breathe::default()).await
+                    }
+                    Effect::Twinkle => {
+                        render_loop(sink, pixels, segment, color, Twinkle::default()).await
+                    }
+                    Effect::Comet => {
+                        render_loop(sink, pixels, segment, color, Comet::default()).await
+                    }
+                    Effect::LarsonScanner => {
+                        render_loop(sink, pixels, segment, color, LarsonScanner::default()).await
+                    }
+                    Effect::Fire2012 => {
+                        render_loop(sink, pixels, segment, color, Fire2012::default()).await
+                    }
+                    Effect::MeteorRain => {
+                        render_loop(sink, pixels, segment, color, MeteorRain::default()).await
+                    }
+                }
+            }
+            program => {
+                let color = program.color().unwrap_or_default();
+                info!("{segment}: ON {},{},{}", color.r, color.g, color.b);
+                pixels.fill(color);
+                sink.write(pixels).await;
             }
         }
     }
 }
 
-#[embassy_executor::task]
-async fn led_task(mut ws2812: Ws2812) {
+/// Writes frames to a `Ws2812` strip, converting to the strip's colour order.
+#[cfg(feature = "device")]
+struct Strip<const SM: usize> {
+    ws2812: Ws2812<SM>,
+    order: ColorOrder,
+    white: Option<WhiteChannel>,
+    words: [u32; MAX_PIXELS],
+}
+
+#[cfg(feature = "device")]
+impl<const SM: usize> FrameSink for Strip<SM> {
+    async fn write(&mut self, pixels: &[RGB]) {
+        // Disabled outputs have no pixels, skip the empty DMA transfer.
+        if pixels.is_empty() {
+            return;
+        }
+
+        for (word, pixel) in self.words.iter_mut().zip(pixels) {
+            *word = match self.white {
+                Some(white) => pixel.to_rgbw_word(self.order, white),
+                None => pixel.to_word(self.order),
+            };
+        }
+
+        self.ws2812.write(&self.words[..pixels.len()]).await;
+    }
+}
+
+#[cfg(feature = "device")]
+impl<const SM: usize> Strip<SM> {
+    /// Turns off the first `len` pixels, used when the strip is shortened.
+    async fn clear(&mut self, len: usize) {
+        let len = len.min(MAX_PIXELS);
+        if len == 0 {
+            return;
+        }
+
+        self.words[..len].fill(0);
+        self.ws2812.write(&self.words[..len]).await;
+    }
+}
+
+/// Writes an output's frame to its strip whenever a segment changes it, or the streamed frame while
+/// a realtime stream is active. The output index matches the state machine.
+#[cfg(feature = "device")]
+async fn drive_output<const SM: usize>(ws2812: Ws2812<SM>) {
+    let mut strip = Strip {
+        ws2812,
+        order: ColorOrder::Rgb,
+        white: None,
+        words: [0; MAX_PIXELS],
+    };
+    let mut frame = [RGB::default(); MAX_PIXELS];
+    let mut len = 0;
+
     loop {
-        let program = LED_CHANNEL.receive().await;
-        program.run::<50, OrderRGB>(&mut ws2812).await;
+        match realtime_deadline() {
+            // Wake when the stream times out to show the segments again.
+            Some(deadline) => {
+                select(FRAME_CHANGED[SM].wait(), Timer::at(deadline)).await;
+            }
+            None => FRAME_CHANGED[SM].wait().await,
+        }
+
+        let settings = StripSettings::get(SM);
+        strip.order = settings.order;
+        if strip.white != settings.white {
+            // Clear with the old word size so no pixels are left lit.
+            strip.clear(len).await;
+            strip.white = settings.white;
+            strip
+                .ws2812
+                .set_pixel_bits(if settings.white.is_some() { 32 } else { 24 });
+        }
+
+        if settings.pixels < len {
+            strip.clear(len).await;
+        }
+        len = settings.pixels;
+
+        if is_realtime() {
+            read_realtime(SM, &mut frame[..len]);
+            limit(&mut frame[..len], &settings.power);
+        } else {
+            read_frame(SM, &mut frame[..len]);
+        }
+        strip.write(&frame[..len]).await;
     }
 }
 
-pub fn spawn_leds(spawner: &Spawner, ws2812: Ws2812) {
-    spawner.spawn(led_task(ws2812)).unwrap();
+/// The buffers a segment draws with. They are kept in statics rather than the LED task, whose
+/// future would otherwise hold them for every segment.
+#[cfg(feature = "device")]
+struct SegmentBuffers {
+    output: Output<SegmentSink>,
+    pixels: [RGB; MAX_PIXELS],
+}
+
+#[cfg(feature = "device")]
+static SEGMENT_BUFFERS: [StaticCell<SegmentBuffers>; MAX_SEGMENTS] = [
+    StaticCell::new(),
+    StaticCell::new(),
+    StaticCell::new(),
+    StaticCell::new(),
+    StaticCell::new(),
+    StaticCell::new(),
+    StaticCell::new(),
+    StaticCell::new(),
+];
+
+/// Runs the programs sent to a single segment.
+#[cfg(feature = "device")]
+async fn drive_segment(segment: usize) {
+    let SegmentBuffers { output, pixels } = SEGMENT_BUFFERS[segment].init_with(|| SegmentBuffers {
+        output: Output::new(SegmentSink::new(), segment),
+        pixels: [RGB::default(); MAX_PIXELS],
+    });
+    let mut last = None;
+
+    loop {
+        let command = LED_CHANNELS[segment].receive().await;
+        let settings = SegmentSettings::get(segment);
+        output.sink().set_segment(settings.segment);
+        output.set_power(settings.power);
+
+        // Custom pixel updates restart the same program, there is no new state to report.
+        let update =
+            matches!(command.program, LedProgram::Custom { .. }) && last == Some(command.program);
+        let len = settings.segment.pixels.min(MAX_PIXELS);
+        if len > 0 && !update {
+            command.program.publish_state(segment).await;
+        }
+        last = Some(command.program);
+        output.start(command.transition, command.program.brightness());
+        command
+            .program
+            .run(output, &mut pixels[..len], segment)
+            .await;
+        output.finish().await;
+    }
+}
+
+#[cfg(feature = "device")]
+#[embassy_executor::task]
+async fn led_task(outputs: Ws2812Outputs) {
+    let Ws2812Outputs(ws0, ws1, ws2, ws3) = outputs;
+
+    join(
+        join4(
+            drive_output(ws0),
+            drive_output(ws1),
+            drive_output(ws2),
+            drive_output(ws3),
+        ),
+        join_array(core::array::from_fn::<_, MAX_SEGMENTS, _>(drive_segment)),
+    )
+    .await;
+}
+
+#[cfg(feature = "device")]
+pub fn spawn_leds(spawner: &Spawner, outputs: Ws2812Outputs) {
+    spawner.spawn(led_task(outputs)).unwrap();
 }
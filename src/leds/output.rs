@@ -0,0 +1,116 @@
+//! The output stage between a running program and the strip. Applies the program's brightness,
+//! cross-fades from the frame that was showing when the program started and keeps the strip
+//! within its power budget.
+
+use embassy_time::{Duration, Instant};
+use portable_atomic::Ordering;
+
+use crate::leds::{
+    blend::{blend_frame, progress, scale},
+    power::{limit, PowerModel},
+    AbortableTicker, FrameSink, CURRENT_MA, FRAME_INTERVAL, MAX_PIXELS, RGB,
+};
+
+/// Wraps a sink, scaling the frames written to it by the current brightness and blending them
+/// with the frame shown when the current transition started. The result is then limited to the
+/// power budget.
+pub struct Output<S: FrameSink> {
+    sink: S,
+    /// The index of the segment, used to watch for new programs and report current.
+    segment: usize,
+    /// The frame shown when the current transition started.
+    from: [RGB; MAX_PIXELS],
+    /// The most recent frame written by the running program, after scaling.
+    target: [RGB; MAX_PIXELS],
+    /// The frame most recently written to the sink.
+    shown: [RGB; MAX_PIXELS],
+    len: usize,
+    brightness: u8,
+    power: PowerModel,
+    start: Instant,
+    duration: Duration,
+}
+
+impl<S: FrameSink> Output<S> {
+    pub fn new(sink: S, segment: usize) -> Self {
+        Self {
+            sink,
+            segment,
+            from: [RGB::default(); MAX_PIXELS],
+            target: [RGB::default(); MAX_PIXELS],
+            shown: [RGB::default(); MAX_PIXELS],
+            len: 0,
+            brightness: 255,
+            power: PowerModel {
+                ma_per_channel: 20,
+                budget_ma: 0,
+                white: None,
+            },
+            start: Instant::now(),
+            duration: Duration::from_ticks(0),
+        }
+    }
+
+    pub fn sink(&mut self) -> &mut S {
+        &mut self.sink
+    }
+
+    pub fn set_power(&mut self, power: PowerModel) {
+        self.power = power;
+    }
+
+    /// Starts a transition from whatever is currently showing to frames at `brightness`. Starting
+    /// a new transition part way through another continues on from the partially blended frame.
+    pub fn start(&mut self, duration: Duration, brightness: u8) {
+        self.from = [RGB::default(); MAX_PIXELS];
+        self.from[..self.len].copy_from_slice(&self.shown[..self.len]);
+        self.brightness = brightness;
+        self.start = Instant::now();
+        self.duration = duration;
+    }
+
+    fn is_fading(&self) -> bool {
+        self.start.elapsed() < self.duration
+    }
+
+    async fn show(&mut self) {
+        let amount = progress(self.start.elapsed().as_millis(), self.duration.as_millis());
+
+        blend_frame(
+            &self.from,
+            &self.target[..self.len],
+            &mut self.shown[..self.len],
+            amount,
+        );
+
+        // Limiting the shown frame means the next transition starts from what was really shown.
+        let current = limit(&mut self.shown[..self.len], &self.power);
+        CURRENT_MA[self.segment].store(current, Ordering::Relaxed);
+
+        self.sink.write(&self.shown[..self.len]).await;
+    }
+
+    /// Continues the transition after a program has written its last frame, returns early if a
+    /// new program is sent to this segment.
+    pub async fn finish(&mut self) {
+        let mut ticker = AbortableTicker::every(FRAME_INTERVAL, self.segment);
+
+        while self.is_fading() {
+            if ticker.next().await {
+                return;
+            }
+
+            self.show().await;
+        }
+    }
+}
+
+impl<S: FrameSink> FrameSink for Output<S> {
+    async fn write(&mut self, pixels: &[RGB]) {
+        self.len = pixels.len().min(MAX_PIXELS);
+        for (target, pixel) in self.target.iter_mut().zip(&pixels[..self.len]) {
+            *target = scale(*pixel, self.brightness);
+        }
+        self.show().await;
+    }
+}
@@ -0,0 +1,157 @@
+//! Estimates the current drawn by a frame and scales frames down to stay within a budget.
+
+use crate::leds::{
+    color::{gamma, Pixel},
+    WhiteChannel, RGB, RGBW,
+};
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct PowerModel {
+    /// The current drawn by a single channel at full intensity.
+    pub ma_per_channel: u16,
+    /// The most current the strip may draw, 0 disables limiting.
+    pub budget_ma: u32,
+    /// The white LED of an RGBW strip, whose channel is derived from the RGB values when the
+    /// frame is sent and so must be counted too.
+    pub white: Option<WhiteChannel>,
+}
+
+/// The estimated current drawn by a frame in milliamps. Channel intensities are gamma corrected
+/// first as that is what the strip actually receives. On RGBW strips the colour is split between
+/// the RGB and white LEDs the same way it is when the frame is sent.
+pub fn estimate_ma(frame: &[RGB], model: &PowerModel) -> u32 {
+    let total: u32 = frame
+        .iter()
+        .map(|pixel| match model.white {
+            Some(white) => {
+                let rgbw = RGBW::from_rgb(pixel.to_rgb(), white);
+                [rgbw.r, rgbw.g, rgbw.b, rgbw.w]
+                    .into_iter()
+                    .map(|value| u32::from(gamma(value)))
+                    .sum::<u32>()
+            }
+            None => {
+                u32::from(gamma(pixel.r)) + u32::from(gamma(pixel.g)) + u32::from(gamma(pixel.b))
+            }
+        })
+        .sum();
+
+    (u64::from(total) * u64::from(model.ma_per_channel) / 255) as u32
+}
+
+/// Scales the frame down if needed so its estimated current is within the budget, returning the
+/// estimated current of the frame that will be shown.
+///
+/// Scaling happens before gamma correction. The gamma curve is below linear so scaling the input
+/// by some factor scales the current by at most that factor and a single pass normally suffices.
+/// Scaling rounds down so any further passes always make progress.
+pub fn limit(frame: &mut [RGB], model: &PowerModel) -> u32 {
+    let mut estimate = estimate_ma(frame, model);
+
+    while model.budget_ma > 0 && estimate > model.budget_ma {
+        let level = (u64::from(model.budget_ma) * 255 / u64::from(estimate)).min(254) as u16;
+        for pixel in frame.iter_mut() {
+            pixel.r = (u16::from(pixel.r) * level / 255) as u8;
+            pixel.g = (u16::from(pixel.g) * level / 255) as u8;
+            pixel.b = (u16::from(pixel.b) * level / 255) as u8;
+        }
+
+        estimate = estimate_ma(frame, model);
+    }
+
+    estimate
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WHITE: RGB = RGB {
+        r: 255,
+        g: 255,
+        b: 255,
+    };
+
+    fn model(budget_ma: u32) -> PowerModel {
+        PowerModel {
+            ma_per_channel: 20,
+            budget_ma,
+            white: None,
+        }
+    }
+
+    fn rgbw_model(budget_ma: u32, white: WhiteChannel) -> PowerModel {
+        PowerModel {
+            white: Some(white),
+            ..model(budget_ma)
+        }
+    }
+
+    #[test]
+    fn estimates_full_white() {
+        assert_eq!(estimate_ma(&[WHITE; 10], &model(0)), 600);
+        assert_eq!(estimate_ma(&[RGB::default(); 10], &model(0)), 0);
+    }
+
+    #[test]
+    fn leaves_frames_under_budget() {
+        let mut frame = [WHITE, RGB { r: 40, g: 0, b: 90 }];
+        let original = frame;
+
+        assert_eq!(
+            limit(&mut frame, &model(1000)),
+            estimate_ma(&original, &model(0))
+        );
+        assert!(frame == original);
+    }
+
+    #[test]
+    fn scales_frames_over_budget() {
+        let mut frame = [WHITE; 50];
+
+        let estimate = limit(&mut frame, &model(1000));
+        assert!(estimate <= 1000);
+        assert!(estimate > 0);
+        assert_eq!(estimate, estimate_ma(&frame, &model(0)));
+        assert!(frame
+            .iter()
+            .all(|pixel| *pixel == frame[0] && pixel.r < 255));
+    }
+
+    #[test]
+    fn zero_budget_is_unlimited() {
+        let mut frame = [WHITE; 50];
+
+        assert_eq!(limit(&mut frame, &model(0)), 3000);
+        assert!(frame == [WHITE; 50]);
+    }
+
+    #[test]
+    fn counts_the_white_channel() {
+        // Neutral white is shown entirely on the white LED.
+        assert_eq!(
+            estimate_ma(&[WHITE; 10], &rgbw_model(0, WhiteChannel::Neutral)),
+            200
+        );
+
+        // A warm white LED can't show full white alone so the blue channel lights up as well.
+        let warm = rgbw_model(0, WhiteChannel::Kelvin(2700));
+        let estimate = estimate_ma(&[WHITE; 10], &warm);
+        assert!(estimate > 200);
+        assert!(estimate < 600);
+
+        // Colours without a white component never reach the white LED.
+        let red = [RGB { r: 255, g: 0, b: 0 }; 10];
+        assert_eq!(estimate_ma(&red, &warm), estimate_ma(&red, &model(0)));
+    }
+
+    #[test]
+    fn limits_rgbw_frames() {
+        let warm = rgbw_model(1000, WhiteChannel::Kelvin(2700));
+        let mut frame = [WHITE; 100];
+
+        let estimate = limit(&mut frame, &warm);
+        assert!(estimate <= 1000);
+        assert_eq!(estimate, estimate_ma(&frame, &warm));
+    }
+}
@@ -0,0 +1,178 @@
+//! Frames streamed from the network by the realtime protocols. While a stream keeps arriving its
+//! frames are shown in place of the segments, whose programs carry on underneath and are shown again
+//! once the stream stops.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+use crate::leds::{segment::FRAME_CHANGED, StripSettings, MAX_OUTPUTS, MAX_PIXELS, RGB};
+
+type Frame = Mutex<CriticalSectionRawMutex, RefCell<[RGB; MAX_PIXELS]>>;
+
+/// The streamed frame of each output being shown.
+static REALTIME_FRAMES: [Frame; MAX_OUTPUTS] = [
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+];
+
+/// Data written since the frames were last shown. Kept apart so a frame split across several
+/// packets is never shown half written, the outputs redraw whenever their segments change.
+static PENDING_FRAMES: [Frame; MAX_OUTPUTS] = [
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+];
+
+/// The protocols that can stream frames.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub enum RealtimeSource {
+    E131,
+    ArtNet,
+    Ddp,
+}
+
+impl RealtimeSource {
+    pub const ALL: [Self; 3] = [Self::E131, Self::ArtNet, Self::Ddp];
+
+    pub const fn name(&self) -> &'static str {
+        match self {
+            Self::E131 => "E1.31",
+            Self::ArtNet => "Art-Net",
+            Self::Ddp => "DDP",
+        }
+    }
+}
+
+/// The source of the current stream and when it times out.
+static REALTIME_UNTIL: Mutex<CriticalSectionRawMutex, Cell<Option<(RealtimeSource, Instant)>>> =
+    Mutex::new(Cell::new(None));
+
+fn current_stream() -> Option<(RealtimeSource, Instant)> {
+    REALTIME_UNTIL
+        .lock(|until| until.get())
+        .filter(|(_, until)| *until > Instant::now())
+}
+
+/// When the current stream times out, `None` when no stream is being shown.
+pub fn realtime_deadline() -> Option<Instant> {
+    current_stream().map(|(_, until)| until)
+}
+
+/// The protocol of the stream being shown.
+pub fn realtime_source() -> Option<RealtimeSource> {
+    current_stream().map(|(source, _)| source)
+}
+
+pub fn is_realtime() -> bool {
+    realtime_deadline().is_some()
+}
+
+fn frames_changed() {
+    for signal in &FRAME_CHANGED {
+        signal.signal(());
+    }
+}
+
+/// Writes RGB channel data to the pixels from `first`, counting through the outputs in order.
+/// Nothing is shown until `show_realtime` is called.
+pub fn write_realtime(first: usize, channels: &[u8]) {
+    let mut colors = channels
+        .chunks_exact(3)
+        .map(|color| RGB {
+            r: color[0],
+            g: color[1],
+            b: color[2],
+        })
+        .peekable();
+    let mut skip = first;
+
+    for (output, frame) in PENDING_FRAMES.iter().enumerate() {
+        if colors.peek().is_none() {
+            break;
+        }
+
+        let len = StripSettings::get(output).pixels;
+        if skip >= len {
+            skip -= len;
+            continue;
+        }
+
+        frame.lock(|frame| {
+            for (pixel, color) in frame.borrow_mut()[skip..len].iter_mut().zip(&mut colors) {
+                *pixel = color;
+            }
+        });
+        skip = 0;
+    }
+}
+
+/// Shows the data written so far in place of the segments until `timeout` passes without another
+/// call.
+pub fn show_realtime(source: RealtimeSource, timeout: Duration) {
+    for (pending, shown) in PENDING_FRAMES.iter().zip(&REALTIME_FRAMES) {
+        pending.lock(|pending| shown.lock(|shown| *shown.borrow_mut() = *pending.borrow()));
+    }
+
+    REALTIME_UNTIL.lock(|until| until.set(Some((source, Instant::now() + timeout))));
+    frames_changed();
+}
+
+/// Ends the current stream, showing the segments again.
+pub fn stop_realtime() {
+    REALTIME_UNTIL.lock(|until| until.set(None));
+    frames_changed();
+}
+
+/// Copies the start of an output's streamed frame into `pixels`.
+#[cfg(any(test, feature = "device"))]
+pub fn read_realtime(output: usize, pixels: &mut [RGB]) {
+    REALTIME_FRAMES[output].lock(|frame| {
+        pixels.copy_from_slice(&frame.borrow()[..pixels.len()]);
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn writes_are_shown_together() {
+        StripSettings::set(
+            0,
+            StripSettings {
+                pixels: 2,
+                ..StripSettings::get(0)
+            },
+        );
+        let read = || {
+            let mut pixels = [RGB::default(); 2];
+            read_realtime(0, &mut pixels);
+            pixels
+        };
+        let red = RGB { r: 255, g: 0, b: 0 };
+        let blue = RGB { r: 0, g: 0, b: 255 };
+
+        write_realtime(0, &[255, 0, 0]);
+        assert!(read() == [RGB::default(); 2]);
+
+        write_realtime(1, &[0, 0, 255]);
+        assert!(read() == [RGB::default(); 2]);
+
+        show_realtime(RealtimeSource::Ddp, Duration::from_secs(1));
+        assert!(read() == [red, blue]);
+
+        // Later writes wait for the next push while the last frame stays on show.
+        write_realtime(0, &[0, 0, 255]);
+        assert!(read() == [red, blue]);
+
+        show_realtime(RealtimeSource::Ddp, Duration::from_secs(1));
+        assert!(read() == [blue, blue]);
+
+        stop_realtime();
+    }
+}
@@ -0,0 +1,151 @@
+//! The render loop that paces animated programs and keeps statistics on how well it is keeping up.
+
+use core::cell::Cell;
+
+use embassy_sync::blocking_mutex::{raw::CriticalSectionRawMutex, Mutex};
+use embassy_time::{Duration, Instant};
+
+use crate::leds::{
+    animations::Animation,
+    effects::{EffectParams, EffectTuning},
+    AbortableTicker, FrameSink, FRAME_INTERVAL, MAX_SEGMENTS, RGB,
+};
+
+/// How often the statistics are updated.
+const STATS_WINDOW: Duration = Duration::from_secs(1);
+
+/// Timing of a segment's render loop over the most recent window.
+#[derive(Clone, Copy, Default, PartialEq, Eq)]
+pub struct RenderStats {
+    /// Frames written in the last second, 0 when no animation is running.
+    pub fps: u32,
+    /// The average time to render and write a frame, in microseconds.
+    pub render_us: u32,
+    /// Frames that took longer than the frame interval since startup.
+    pub overruns: u32,
+}
+
+static RENDER_STATS: Mutex<CriticalSectionRawMutex, Cell<[RenderStats; MAX_SEGMENTS]>> =
+    Mutex::new(Cell::new(
+        [RenderStats {
+            fps: 0,
+            render_us: 0,
+            overruns: 0,
+        }; MAX_SEGMENTS],
+    ));
+
+impl RenderStats {
+    pub fn get(segment: usize) -> Self {
+        RENDER_STATS.lock(|stats| stats.get()[segment])
+    }
+
+    fn set(segment: usize, stats: RenderStats) {
+        RENDER_STATS.lock(|cell| {
+            let mut all = cell.get();
+            all[segment] = stats;
+            cell.set(all);
+        });
+    }
+
+    /// The statistics of all segments together. As the segments share a core the rate and render
+    /// time are those of the busiest segment.
+    pub fn combined() -> Self {
+        let all = RENDER_STATS.lock(|stats| stats.get());
+        all.iter()
+            .fold(RenderStats::default(), |combined, stats| RenderStats {
+                fps: combined.fps.max(stats.fps),
+                render_us: combined.render_us.max(stats.render_us),
+                overruns: combined.overruns + stats.overruns,
+            })
+    }
+}
+
+/// Accumulates frame timings and publishes them to `RENDER_STATS` once per window.
+struct FrameTimer {
+    segment: usize,
+    window_start: Instant,
+    frames: u32,
+    busy: Duration,
+    overruns: u32,
+}
+
+impl FrameTimer {
+    fn new(segment: usize) -> Self {
+        Self {
+            segment,
+            window_start: Instant::now(),
+            frames: 0,
+            busy: Duration::from_ticks(0),
+            overruns: RenderStats::get(segment).overruns,
+        }
+    }
+
+    fn record(&mut self, frame_time: Duration) {
+        self.frames += 1;
+        self.busy += frame_time;
+        if frame_time > FRAME_INTERVAL {
+            self.overruns += 1;
+        }
+
+        let window = self.window_start.elapsed();
+        if window >= STATS_WINDOW {
+            RenderStats::set(
+                self.segment,
+                RenderStats {
+                    fps: (u64::from(self.frames) * 1_000_000 / window.as_micros().max(1)) as u32,
+                    render_us: (self.busy.as_micros() / u64::from(self.frames)) as u32,
+                    overruns: self.overruns,
+                },
+            );
+
+            self.window_start = Instant::now();
+            self.frames = 0;
+            self.busy = Duration::from_ticks(0);
+        }
+    }
+
+    /// Marks the segment as idle, keeping the overrun count.
+    fn stop(self) {
+        RenderStats::set(
+            self.segment,
+            RenderStats {
+                fps: 0,
+                render_us: 0,
+                overruns: self.overruns,
+            },
+        );
+    }
+}
+
+/// Renders and writes a frame of `animation` every `FRAME_INTERVAL` until a new program is sent to
+/// `segment`. Each frame uses the segment's current tuning, so changes to it are picked up as they
+/// are made.
+pub async fn render_loop<S: FrameSink>(
+    sink: &mut S,
+    pixels: &mut [RGB],
+    segment: usize,
+    color: RGB,
+    mut animation: impl Animation,
+) {
+    let mut ticker = AbortableTicker::every(FRAME_INTERVAL, segment);
+    let mut timer = FrameTimer::new(segment);
+    let start = Instant::now();
+    animation.init(
+        pixels.len(),
+        &EffectParams::new(color, EffectTuning::get(segment)),
+    );
+
+    loop {
+        let frame_start = Instant::now();
+        let params = EffectParams::new(color, EffectTuning::get(segment));
+        animation.render(pixels, frame_start - start, &params);
+        sink.write(pixels).await;
+        timer.record(frame_start.elapsed());
+
+        if ticker.next().await {
+            break;
+        }
+    }
+
+    timer.stop();
+}
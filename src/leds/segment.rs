@@ -0,0 +1,164 @@
+//! Segments divide the outputs into runs of pixels that each show their own program. Each segment
+//! draws into its output's frame, which is written to the strip whenever it changes.
+
+use core::cell::{Cell, RefCell};
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+
+use crate::leds::{FrameSink, PowerModel, MAX_OUTPUTS, MAX_PIXELS, MAX_SEGMENTS, RGB};
+
+/// Where a segment's pixels are on the outputs.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct Segment {
+    pub output: usize,
+    /// The first pixel of the output in the segment.
+    pub start: usize,
+    /// 0 disables the segment.
+    pub pixels: usize,
+    /// Runs the segment's programs from its last pixel back to its first.
+    pub reverse: bool,
+}
+
+impl Segment {
+    pub const DISABLED: Self = Self {
+        output: 0,
+        start: 0,
+        pixels: 0,
+        reverse: false,
+    };
+
+    pub fn is_enabled(&self) -> bool {
+        self.pixels > 0
+    }
+
+    /// The position in the output's frame of a pixel of the segment.
+    fn position(&self, pixel: usize) -> usize {
+        if self.reverse {
+            self.start + self.pixels - 1 - pixel
+        } else {
+            self.start + pixel
+        }
+    }
+}
+
+/// The layout and power budget of each segment, read each time a program is started.
+static SEGMENT_SETTINGS: Mutex<CriticalSectionRawMutex, Cell<[SegmentSettings; MAX_SEGMENTS]>> =
+    Mutex::new(Cell::new(
+        [SegmentSettings {
+            segment: Segment::DISABLED,
+            power: PowerModel {
+                ma_per_channel: 20,
+                budget_ma: 0,
+                white: None,
+            },
+        }; MAX_SEGMENTS],
+    ));
+
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct SegmentSettings {
+    pub segment: Segment,
+    pub power: PowerModel,
+}
+
+impl SegmentSettings {
+    pub fn get(segment: usize) -> Self {
+        SEGMENT_SETTINGS.lock(|settings| settings.get()[segment])
+    }
+
+    pub fn set(segment: usize, settings: SegmentSettings) {
+        let mut layout = settings.segment;
+        if layout.output >= MAX_OUTPUTS {
+            layout = Segment::DISABLED;
+        }
+        layout.start = layout.start.min(MAX_PIXELS);
+        layout.pixels = layout.pixels.min(MAX_PIXELS - layout.start);
+
+        SEGMENT_SETTINGS.lock(|cell| {
+            let mut all = cell.get();
+            all[segment] = SegmentSettings {
+                segment: layout,
+                ..settings
+            };
+            cell.set(all);
+        });
+    }
+}
+
+type Frame = Mutex<CriticalSectionRawMutex, RefCell<[RGB; MAX_PIXELS]>>;
+
+/// The frame of each output, composited from its segments.
+static FRAMES: [Frame; MAX_OUTPUTS] = [
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+    Mutex::new(RefCell::new([RGB { r: 0, g: 0, b: 0 }; MAX_PIXELS])),
+];
+
+/// Signalled when a segment changes its output's frame.
+pub static FRAME_CHANGED: [Signal<CriticalSectionRawMutex, ()>; MAX_OUTPUTS] =
+    [Signal::new(), Signal::new(), Signal::new(), Signal::new()];
+
+/// Copies the start of an output's frame into `pixels`.
+#[cfg(feature = "device")]
+pub fn read_frame(output: usize, pixels: &mut [RGB]) {
+    FRAMES[output].lock(|frame| {
+        let frame = frame.borrow();
+        pixels.copy_from_slice(&frame[..pixels.len()]);
+    });
+}
+
+/// Draws frames into a segment of its output's frame.
+pub struct SegmentSink {
+    segment: Segment,
+}
+
+impl SegmentSink {
+    pub fn new() -> Self {
+        Self {
+            segment: Segment::DISABLED,
+        }
+    }
+
+    /// Moves the sink to a new layout, turning off the pixels it previously covered.
+    pub fn set_segment(&mut self, segment: Segment) {
+        if segment == self.segment {
+            return;
+        }
+
+        if self.segment.is_enabled() {
+            let old = self.segment;
+            FRAMES[old.output].lock(|frame| {
+                frame.borrow_mut()[old.start..old.start + old.pixels].fill(RGB::default());
+            });
+            FRAME_CHANGED[old.output].signal(());
+        }
+
+        self.segment = segment;
+    }
+}
+
+impl Default for SegmentSink {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl FrameSink for SegmentSink {
+    async fn write(&mut self, pixels: &[RGB]) {
+        let segment = self.segment;
+        if !segment.is_enabled() {
+            return;
+        }
+
+        FRAMES[segment.output].lock(|frame| {
+            let mut frame = frame.borrow_mut();
+            for (i, pixel) in pixels.iter().take(segment.pixels).enumerate() {
+                frame[segment.position(i)] = *pixel;
+            }
+        });
+        FRAME_CHANGED[segment.output].signal(());
+    }
+}
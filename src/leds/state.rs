@@ -0,0 +1,41 @@
+//! The state reported to Home Assistant, following the JSON schema for MQTT lights.
+
+use serde::Serialize;
+
+/// The colour in whichever mode Home Assistant last used to set it.
+#[derive(Serialize)]
+#[serde(untagged)]
+pub enum ColorState {
+    Rgb {
+        r: u8,
+        g: u8,
+        b: u8,
+        /// Only reported for RGBW strips.
+        #[serde(skip_serializing_if = "Option::is_none")]
+        w: Option<u8>,
+    },
+    Hs {
+        h: f32,
+        s: f32,
+    },
+    Xy {
+        x: f32,
+        y: f32,
+    },
+}
+
+#[derive(Serialize)]
+pub struct ReportedState {
+    pub state: &'static str,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub brightness: Option<u8>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_mode: Option<&'static str>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color: Option<ColorState>,
+    /// In mireds.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub color_temp: Option<u16>,
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub effect: Option<&'static str>,
+}
@@ -0,0 +1,523 @@
+//! A DHCP server just capable enough to give the few devices joining the setup access point an
+//! address, with the device itself as their router and name server.
+
+use core::net::Ipv4Addr;
+
+#[cfg(feature = "device")]
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpEndpoint, Stack,
+};
+#[cfg(feature = "device")]
+use log::{info, warn};
+
+use crate::provisioning::{AP_ADDRESS, PORTAL_URL};
+
+/// The UDP port requests are sent to.
+pub const SERVER_PORT: u16 = 67;
+/// The UDP port replies are sent to.
+pub const CLIENT_PORT: u16 = 68;
+
+const OP_REQUEST: u8 = 1;
+const OP_REPLY: u8 = 2;
+const HTYPE_ETHERNET: u8 = 1;
+const MAGIC_COOKIE: [u8; 4] = [99, 130, 83, 99];
+
+/// The length of a message before its options.
+const HEADER_LEN: usize = 240;
+
+/// Some clients ignore replies shorter than a BOOTP message.
+pub const MIN_REPLY_LEN: usize = 300;
+
+/// The largest reply, the minimum every client must accept.
+pub const MAX_REPLY_LEN: usize = 548;
+
+const OPTION_PAD: u8 = 0;
+const OPTION_SUBNET_MASK: u8 = 1;
+const OPTION_ROUTER: u8 = 3;
+const OPTION_DNS: u8 = 6;
+const OPTION_REQUESTED_ADDRESS: u8 = 50;
+const OPTION_LEASE_TIME: u8 = 51;
+const OPTION_MESSAGE_TYPE: u8 = 53;
+const OPTION_SERVER_ID: u8 = 54;
+const OPTION_CAPTIVE_PORTAL: u8 = 114;
+const OPTION_END: u8 = 255;
+
+/// How long an address is leased for.
+const LEASE_SECS: u32 = 3600;
+
+/// The most devices that can join the access point at once.
+pub const MAX_LEASES: usize = 8;
+
+/// The first address handed out, the rest follow it.
+const FIRST_LEASE: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 10);
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DhcpError {
+    /// The packet ends before its header or an option does.
+    Truncated,
+    /// The packet isn't a DHCP request from an Ethernet or Wi-Fi client.
+    NotRequest,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum MessageType {
+    Discover,
+    Offer,
+    Request,
+    Ack,
+    Nak,
+    Release,
+    Other(u8),
+}
+
+impl MessageType {
+    fn from_u8(value: u8) -> Self {
+        match value {
+            1 => Self::Discover,
+            2 => Self::Offer,
+            3 => Self::Request,
+            5 => Self::Ack,
+            6 => Self::Nak,
+            7 => Self::Release,
+            other => Self::Other(other),
+        }
+    }
+
+    fn to_u8(self) -> u8 {
+        match self {
+            Self::Discover => 1,
+            Self::Offer => 2,
+            Self::Request => 3,
+            Self::Ack => 5,
+            Self::Nak => 6,
+            Self::Release => 7,
+            Self::Other(other) => other,
+        }
+    }
+}
+
+fn address(bytes: &[u8]) -> Ipv4Addr {
+    Ipv4Addr::new(bytes[0], bytes[1], bytes[2], bytes[3])
+}
+
+pub struct DhcpRequest {
+    pub message_type: MessageType,
+    pub xid: [u8; 4],
+    pub flags: [u8; 2],
+    /// The client's hardware address.
+    pub client: [u8; 6],
+    /// The address the client already has, unspecified when it has none.
+    pub client_address: Ipv4Addr,
+    /// The address the client asked for.
+    pub requested: Option<Ipv4Addr>,
+    /// The server the client chose to take its address from.
+    pub server: Option<Ipv4Addr>,
+}
+
+impl DhcpRequest {
+    /// The address the client wants, either one it asked for or the one it already has.
+    pub fn wanted_address(&self) -> Option<Ipv4Addr> {
+        self.requested
+            .or(Some(self.client_address).filter(|address| !address.is_unspecified()))
+    }
+}
+
+/// Parses a request sent by a client.
+pub fn parse(packet: &[u8]) -> Result<DhcpRequest, DhcpError> {
+    if packet.len() < HEADER_LEN {
+        return Err(DhcpError::Truncated);
+    }
+    if packet[0] != OP_REQUEST
+        || packet[1] != HTYPE_ETHERNET
+        || packet[2] != 6
+        || packet[236..240] != MAGIC_COOKIE
+    {
+        return Err(DhcpError::NotRequest);
+    }
+
+    let mut request = DhcpRequest {
+        message_type: MessageType::Other(0),
+        xid: packet[4..8].try_into().unwrap(),
+        flags: packet[10..12].try_into().unwrap(),
+        client: packet[28..34].try_into().unwrap(),
+        client_address: address(&packet[12..16]),
+        requested: None,
+        server: None,
+    };
+
+    let mut options = &packet[HEADER_LEN..];
+    while let Some((&code, rest)) = options.split_first() {
+        match code {
+            OPTION_PAD => {
+                options = rest;
+                continue;
+            }
+            OPTION_END => break,
+            _ => {}
+        }
+
+        let (&len, rest) = rest.split_first().ok_or(DhcpError::Truncated)?;
+        let value = rest.get(..usize::from(len)).ok_or(DhcpError::Truncated)?;
+        match (code, value.len()) {
+            (OPTION_MESSAGE_TYPE, 1) => request.message_type = MessageType::from_u8(value[0]),
+            (OPTION_REQUESTED_ADDRESS, 4) => request.requested = Some(address(value)),
+            (OPTION_SERVER_ID, 4) => request.server = Some(address(value)),
+            _ => {}
+        }
+        options = &rest[usize::from(len)..];
+    }
+
+    if request.message_type == MessageType::Other(0) {
+        return Err(DhcpError::NotRequest);
+    }
+
+    Ok(request)
+}
+
+/// A reply from the access point to a client.
+pub struct DhcpReply<'a> {
+    pub message_type: MessageType,
+    pub request: &'a DhcpRequest,
+    /// The address offered or acknowledged, unused when refusing a request.
+    pub address: Ipv4Addr,
+}
+
+struct OptionWriter<'a> {
+    buf: &'a mut [u8],
+    cursor: usize,
+}
+
+impl OptionWriter<'_> {
+    fn option(&mut self, code: u8, value: &[u8]) {
+        self.buf[self.cursor] = code;
+        self.buf[self.cursor + 1] = value.len() as u8;
+        self.buf[self.cursor + 2..self.cursor + 2 + value.len()].copy_from_slice(value);
+        self.cursor += 2 + value.len();
+    }
+}
+
+impl DhcpReply<'_> {
+    /// Encodes the reply, returning its length.
+    pub fn encode(&self, buf: &mut [u8; MAX_REPLY_LEN]) -> usize {
+        buf.fill(0);
+
+        buf[0] = OP_REPLY;
+        buf[1] = HTYPE_ETHERNET;
+        buf[2] = 6;
+        buf[4..8].copy_from_slice(&self.request.xid);
+        buf[10..12].copy_from_slice(&self.request.flags);
+        if self.message_type != MessageType::Nak {
+            buf[16..20].copy_from_slice(&self.address.octets());
+            buf[20..24].copy_from_slice(&AP_ADDRESS.octets());
+        }
+        buf[28..34].copy_from_slice(&self.request.client);
+        buf[236..240].copy_from_slice(&MAGIC_COOKIE);
+
+        let mut options = OptionWriter {
+            buf: &mut buf[..],
+            cursor: HEADER_LEN,
+        };
+        options.option(OPTION_MESSAGE_TYPE, &[self.message_type.to_u8()]);
+        options.option(OPTION_SERVER_ID, &AP_ADDRESS.octets());
+        if self.message_type != MessageType::Nak {
+            options.option(OPTION_LEASE_TIME, &LEASE_SECS.to_be_bytes());
+            options.option(OPTION_SUBNET_MASK, &[255, 255, 255, 0]);
+            options.option(OPTION_ROUTER, &AP_ADDRESS.octets());
+            options.option(OPTION_DNS, &AP_ADDRESS.octets());
+            options.option(OPTION_CAPTIVE_PORTAL, PORTAL_URL.as_bytes());
+        }
+        options.buf[options.cursor] = OPTION_END;
+
+        (options.cursor + 1).max(MIN_REPLY_LEN)
+    }
+}
+
+/// The addresses handed out to clients, reusing the oldest once they run out.
+pub struct Leases {
+    clients: [Option<[u8; 6]>; MAX_LEASES],
+    next: usize,
+}
+
+impl Leases {
+    pub const fn new() -> Self {
+        Self {
+            clients: [None; MAX_LEASES],
+            next: 0,
+        }
+    }
+
+    fn address(index: usize) -> Ipv4Addr {
+        let [a, b, c, d] = FIRST_LEASE.octets();
+        Ipv4Addr::new(a, b, c, d + index as u8)
+    }
+
+    /// The address leased to a client, if it has one.
+    pub fn find(&self, client: &[u8; 6]) -> Option<Ipv4Addr> {
+        self.clients
+            .iter()
+            .position(|leased| leased.as_ref() == Some(client))
+            .map(Self::address)
+    }
+
+    /// The address leased to a client, leasing it one if needed.
+    pub fn lease(&mut self, client: &[u8; 6]) -> Ipv4Addr {
+        if let Some(address) = self.find(client) {
+            return address;
+        }
+
+        let index = match self.clients.iter().position(Option::is_none) {
+            Some(index) => index,
+            None => {
+                let index = self.next;
+                self.next = (self.next + 1) % MAX_LEASES;
+                index
+            }
+        };
+        self.clients[index] = Some(*client);
+        Self::address(index)
+    }
+
+    pub fn release(&mut self, client: &[u8; 6]) {
+        for leased in &mut self.clients {
+            if leased.as_ref() == Some(client) {
+                *leased = None;
+            }
+        }
+    }
+}
+
+impl Default for Leases {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+/// Answers a request, `None` when it needs no reply.
+pub fn respond(request: &DhcpRequest, leases: &mut Leases) -> Option<(MessageType, Ipv4Addr)> {
+    match request.message_type {
+        MessageType::Discover => Some((MessageType::Offer, leases.lease(&request.client))),
+        MessageType::Request => {
+            // The client took an address from another server.
+            if request.server.is_some_and(|server| server != AP_ADDRESS) {
+                leases.release(&request.client);
+                return None;
+            }
+
+            let address = leases.lease(&request.client);
+            if request.wanted_address() == Some(address) {
+                Some((MessageType::Ack, address))
+            } else {
+                Some((MessageType::Nak, address))
+            }
+        }
+        MessageType::Release => {
+            leases.release(&request.client);
+            None
+        }
+        _ => None,
+    }
+}
+
+/// Hands out addresses to the devices joining the setup access point.
+#[cfg(feature = "device")]
+#[embassy_executor::task]
+pub async fn dhcp_task(network: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 2];
+    let mut rx_buffer = [0; 2 * MAX_REPLY_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 2];
+    let mut tx_buffer = [0; 2 * MAX_REPLY_LEN];
+    let mut socket = UdpSocket::new(
+        network,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(SERVER_PORT) {
+        warn!("Failed to listen for DHCP: {e:?}");
+        return;
+    }
+
+    let mut packet = [0; MAX_REPLY_LEN];
+    let mut reply = [0; MAX_REPLY_LEN];
+    let mut leases = Leases::new();
+    // Clients without an address can only be reached by broadcast.
+    let clients = IpEndpoint::new(Ipv4Addr::BROADCAST.into(), CLIENT_PORT);
+
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut packet).await else {
+            continue;
+        };
+        let Ok(request) = parse(&packet[..len]) else {
+            continue;
+        };
+        let Some((message_type, address)) = respond(&request, &mut leases) else {
+            continue;
+        };
+
+        if message_type == MessageType::Ack {
+            info!("Leased {address} to a setup client");
+        }
+
+        let len = DhcpReply {
+            message_type,
+            request: &request,
+            address,
+        }
+        .encode(&mut reply);
+
+        if let Err(e) = socket.send_to(&reply[..len], clients).await {
+            warn!("Failed to send a DHCP reply: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const CLIENT: [u8; 6] = [2, 0, 0, 0, 0, 1];
+
+    fn packet(client: [u8; 6], options: &[u8]) -> Vec<u8> {
+        let mut packet = std::vec![0; HEADER_LEN];
+        packet[0] = OP_REQUEST;
+        packet[1] = HTYPE_ETHERNET;
+        packet[2] = 6;
+        packet[4..8].copy_from_slice(&[1, 2, 3, 4]);
+        packet[28..34].copy_from_slice(&client);
+        packet[236..240].copy_from_slice(&MAGIC_COOKIE);
+        packet.extend_from_slice(options);
+        packet
+    }
+
+    fn discover(client: [u8; 6]) -> DhcpRequest {
+        parse(&packet(client, &[OPTION_MESSAGE_TYPE, 1, 1, OPTION_END])).unwrap()
+    }
+
+    #[test]
+    fn parses_requests() {
+        let options = [
+            OPTION_PAD,
+            OPTION_MESSAGE_TYPE,
+            1,
+            3,
+            OPTION_REQUESTED_ADDRESS,
+            4,
+            192,
+            168,
+            4,
+            10,
+            OPTION_SERVER_ID,
+            4,
+            192,
+            168,
+            4,
+            1,
+            OPTION_END,
+            // Anything after the end is ignored.
+            OPTION_MESSAGE_TYPE,
+        ];
+        let request = parse(&packet(CLIENT, &options)).unwrap();
+
+        assert_eq!(request.message_type, MessageType::Request);
+        assert_eq!(request.xid, [1, 2, 3, 4]);
+        assert_eq!(request.client, CLIENT);
+        assert_eq!(request.requested, Some(FIRST_LEASE));
+        assert_eq!(request.server, Some(AP_ADDRESS));
+        assert_eq!(request.wanted_address(), Some(FIRST_LEASE));
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let packet = packet(CLIENT, &[OPTION_MESSAGE_TYPE, 1, 1]);
+        assert_eq!(
+            parse(&packet[..HEADER_LEN - 1]).err(),
+            Some(DhcpError::Truncated)
+        );
+
+        // An option's length or value runs past the end.
+        for len in [HEADER_LEN + 1, HEADER_LEN + 2] {
+            assert_eq!(parse(&packet[..len]).err(), Some(DhcpError::Truncated));
+        }
+        let mut long = packet.clone();
+        long[HEADER_LEN + 1] = 200;
+        assert_eq!(parse(&long).err(), Some(DhcpError::Truncated));
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        let mut reply = packet(CLIENT, &[OPTION_MESSAGE_TYPE, 1, 1]);
+        reply[0] = OP_REPLY;
+        assert_eq!(parse(&reply).err(), Some(DhcpError::NotRequest));
+
+        // BOOTP requests have no message type.
+        let bootp = packet(CLIENT, &[OPTION_END]);
+        assert_eq!(parse(&bootp).err(), Some(DhcpError::NotRequest));
+    }
+
+    #[test]
+    fn offers_and_acknowledges_the_same_address() {
+        let mut leases = Leases::new();
+        let (message_type, offered) = respond(&discover(CLIENT), &mut leases).unwrap();
+        assert_eq!(message_type, MessageType::Offer);
+        assert_eq!(offered, FIRST_LEASE);
+
+        let mut options = std::vec![OPTION_MESSAGE_TYPE, 1, 3, OPTION_REQUESTED_ADDRESS, 4];
+        options.extend_from_slice(&offered.octets());
+        let request = parse(&packet(CLIENT, &options)).unwrap();
+        assert_eq!(
+            respond(&request, &mut leases),
+            Some((MessageType::Ack, offered))
+        );
+
+        // Asking for someone else's address is refused.
+        let mut options = std::vec![OPTION_MESSAGE_TYPE, 1, 3, OPTION_REQUESTED_ADDRESS, 4];
+        options.extend_from_slice(&Leases::address(3).octets());
+        let request = parse(&packet(CLIENT, &options)).unwrap();
+        assert_eq!(respond(&request, &mut leases).unwrap().0, MessageType::Nak);
+    }
+
+    #[test]
+    fn reuses_leases_when_full() {
+        let mut leases = Leases::new();
+        for i in 0..MAX_LEASES {
+            let client = [2, 0, 0, 0, 1, i as u8];
+            assert_eq!(leases.lease(&client), Leases::address(i));
+        }
+
+        // Clients that already have a lease keep it.
+        assert_eq!(leases.lease(&[2, 0, 0, 0, 1, 3]), Leases::address(3));
+
+        // New clients take over the oldest leases in turn.
+        let (_, address) = respond(&discover(CLIENT), &mut leases).unwrap();
+        assert_eq!(address, Leases::address(0));
+        assert_eq!(leases.find(&[2, 0, 0, 0, 1, 0]), None);
+        assert_eq!(leases.lease(&[2, 0, 0, 0, 2, 0]), Leases::address(1));
+
+        // Released addresses are handed out before any more are taken over.
+        leases.release(&[2, 0, 0, 0, 1, 5]);
+        assert_eq!(leases.lease(&[2, 0, 0, 0, 2, 1]), Leases::address(5));
+    }
+
+    #[test]
+    fn encodes_replies() {
+        let request = discover(CLIENT);
+        let mut buf = [0; MAX_REPLY_LEN];
+        let len = DhcpReply {
+            message_type: MessageType::Offer,
+            request: &request,
+            address: FIRST_LEASE,
+        }
+        .encode(&mut buf);
+
+        assert!(len >= MIN_REPLY_LEN);
+        assert_eq!(buf[0], OP_REPLY);
+        assert_eq!(buf[4..8], request.xid);
+        assert_eq!(buf[16..20], FIRST_LEASE.octets());
+        assert_eq!(buf[28..34], CLIENT);
+        assert_eq!(buf[HEADER_LEN..HEADER_LEN + 3], [OPTION_MESSAGE_TYPE, 1, 2]);
+    }
+}
@@ -0,0 +1,230 @@
+//! A name server that answers every lookup with the device's own address, which is what makes
+//! clients joining the setup access point notice the captive portal.
+
+use core::net::Ipv4Addr;
+
+#[cfg(feature = "device")]
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+#[cfg(feature = "device")]
+use log::warn;
+
+#[cfg(feature = "device")]
+use crate::provisioning::AP_ADDRESS;
+
+/// The UDP port lookups are sent to.
+pub const PORT: u16 = 53;
+
+/// The largest message sent over UDP.
+pub const MAX_MESSAGE_LEN: usize = 512;
+
+const HEADER_LEN: usize = 12;
+const TYPE_A: u16 = 1;
+const CLASS_IN: u16 = 1;
+const TTL_SECS: u32 = 60;
+
+/// The length of an answer pointing the question's name at an address.
+const ANSWER_LEN: usize = 16;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DnsError {
+    /// The message ends before its question does.
+    Truncated,
+    /// The message isn't a standard query with a question.
+    NotQuery,
+    /// The answer won't fit in the buffer.
+    BufferTooSmall,
+}
+
+/// Answers a query with `address` for any name, returning the length of the answer. Questions for
+/// anything other than an IPv4 address get an empty answer.
+pub fn answer(query: &[u8], address: Ipv4Addr, buf: &mut [u8]) -> Result<usize, DnsError> {
+    if query.len() < HEADER_LEN {
+        return Err(DnsError::Truncated);
+    }
+    // A response, or an opcode other than a standard query.
+    if query[2] & 0xF8 != 0 {
+        return Err(DnsError::NotQuery);
+    }
+    if u16::from_be_bytes([query[4], query[5]]) == 0 {
+        return Err(DnsError::NotQuery);
+    }
+
+    // Only the first question is answered, nobody sends more.
+    let mut cursor = HEADER_LEN;
+    loop {
+        let len = *query.get(cursor).ok_or(DnsError::Truncated)?;
+        if len == 0 {
+            cursor += 1;
+            break;
+        }
+        // Questions never compress their name.
+        if len & 0xC0 != 0 {
+            return Err(DnsError::NotQuery);
+        }
+        cursor += 1 + usize::from(len);
+    }
+    let fields = query.get(cursor..cursor + 4).ok_or(DnsError::Truncated)?;
+    let record_type = u16::from_be_bytes([fields[0], fields[1]]);
+    let class = u16::from_be_bytes([fields[2], fields[3]]);
+    let question = &query[HEADER_LEN..cursor + 4];
+
+    let answers = u16::from(record_type == TYPE_A && class == CLASS_IN);
+    let len = HEADER_LEN + question.len() + usize::from(answers) * ANSWER_LEN;
+    if buf.len() < len {
+        return Err(DnsError::BufferTooSmall);
+    }
+
+    buf[..2].copy_from_slice(&query[..2]);
+    // An authoritative response, copying whether recursion was desired.
+    buf[2] = 0x84 | (query[2] & 0x01);
+    // Recursion available, no error.
+    buf[3] = 0x80;
+    buf[4..6].copy_from_slice(&1_u16.to_be_bytes());
+    buf[6..8].copy_from_slice(&answers.to_be_bytes());
+    buf[8..12].fill(0);
+    buf[HEADER_LEN..HEADER_LEN + question.len()].copy_from_slice(question);
+
+    if answers > 0 {
+        let record = &mut buf[HEADER_LEN + question.len()..len];
+        // A pointer to the name in the question.
+        record[..2].copy_from_slice(&[0xC0, HEADER_LEN as u8]);
+        record[2..4].copy_from_slice(&TYPE_A.to_be_bytes());
+        record[4..6].copy_from_slice(&CLASS_IN.to_be_bytes());
+        record[6..10].copy_from_slice(&TTL_SECS.to_be_bytes());
+        record[10..12].copy_from_slice(&4_u16.to_be_bytes());
+        record[12..16].copy_from_slice(&address.octets());
+    }
+
+    Ok(len)
+}
+
+/// Answers every lookup from the devices joining the setup access point with the device itself.
+#[cfg(feature = "device")]
+#[embassy_executor::task]
+pub async fn dns_task(network: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 4 * MAX_MESSAGE_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 4];
+    let mut tx_buffer = [0; 4 * MAX_MESSAGE_LEN];
+    let mut socket = UdpSocket::new(
+        network,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(PORT) {
+        warn!("Failed to listen for DNS: {e:?}");
+        return;
+    }
+
+    let mut query = [0; MAX_MESSAGE_LEN];
+    let mut reply = [0; MAX_MESSAGE_LEN];
+
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut query).await else {
+            continue;
+        };
+        let Ok(len) = answer(&query[..len], AP_ADDRESS, &mut reply) else {
+            continue;
+        };
+
+        if let Err(e) = socket.send_to(&reply[..len], meta.endpoint).await {
+            warn!("Failed to send a DNS answer: {e:?}");
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    const ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+    const NAME: &[u8] = b"\x07example\x03com\x00";
+
+    fn query(name: &[u8], record_type: u16) -> Vec<u8> {
+        // ID 0x1234, recursion desired, one question.
+        let mut query = std::vec![0x12, 0x34, 0x01, 0x00, 0, 1, 0, 0, 0, 0, 0, 0];
+        query.extend_from_slice(name);
+        query.extend_from_slice(&record_type.to_be_bytes());
+        query.extend_from_slice(&CLASS_IN.to_be_bytes());
+        query
+    }
+
+    #[test]
+    fn answers_with_the_address() {
+        let query = query(NAME, TYPE_A);
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let len = answer(&query, ADDRESS, &mut buf).unwrap();
+
+        assert_eq!(len, query.len() + ANSWER_LEN);
+        assert_eq!(buf[..2], [0x12, 0x34]);
+        assert_eq!(buf[2], 0x85);
+        assert_eq!(buf[6..8], [0, 1]);
+        assert_eq!(buf[HEADER_LEN..query.len()], query[HEADER_LEN..]);
+        assert_eq!(buf[len - 4..len], ADDRESS.octets());
+    }
+
+    #[test]
+    fn leaves_other_types_unanswered() {
+        // AAAA.
+        let query = query(NAME, 28);
+        let mut buf = [0; MAX_MESSAGE_LEN];
+        let len = answer(&query, ADDRESS, &mut buf).unwrap();
+
+        assert_eq!(len, query.len());
+        assert_eq!(buf[6..8], [0, 0]);
+    }
+
+    #[test]
+    fn rejects_compressed_names() {
+        let query = query(b"\x07example\xC0\x0C", TYPE_A);
+        let mut buf = [0; MAX_MESSAGE_LEN];
+
+        assert_eq!(answer(&query, ADDRESS, &mut buf), Err(DnsError::NotQuery));
+    }
+
+    #[test]
+    fn rejects_truncated_queries() {
+        let query = query(NAME, TYPE_A);
+        let mut buf = [0; MAX_MESSAGE_LEN];
+
+        for len in [
+            0,
+            HEADER_LEN - 1,
+            HEADER_LEN,
+            HEADER_LEN + 4,
+            query.len() - 1,
+        ] {
+            assert_eq!(
+                answer(&query[..len], ADDRESS, &mut buf),
+                Err(DnsError::Truncated)
+            );
+        }
+        assert_eq!(
+            answer(&query, ADDRESS, &mut buf[..query.len()]),
+            Err(DnsError::BufferTooSmall)
+        );
+    }
+
+    #[test]
+    fn rejects_responses_and_empty_queries() {
+        let mut buf = [0; MAX_MESSAGE_LEN];
+
+        let mut response = query(NAME, TYPE_A);
+        response[2] |= 0x80;
+        assert_eq!(
+            answer(&response, ADDRESS, &mut buf),
+            Err(DnsError::NotQuery)
+        );
+
+        let mut empty = query(NAME, TYPE_A);
+        empty[5] = 0;
+        assert_eq!(answer(&empty, ADDRESS, &mut buf), Err(DnsError::NotQuery));
+    }
+}
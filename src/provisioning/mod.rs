@@ -0,0 +1,36 @@
+//! Setting up a device that can't join its network. The device starts an open access point of its
+//! own, hands out addresses to whatever joins and answers every name lookup with its own address
+//! so phones and laptops open the configuration page as a captive portal. Saving new settings
+//! there reboots the device back onto the network, as does leaving the access point running for a
+//! while when a network is already configured.
+//!
+//! The DHCP and DNS servers only implement what those clients need. `is_provisioning` tells the web
+//! server to answer them with the configuration page.
+
+use core::net::Ipv4Addr;
+
+use portable_atomic::{AtomicBool, Ordering};
+
+pub mod dhcp;
+pub mod dns;
+
+/// The address of the device on its access point.
+pub const AP_ADDRESS: Ipv4Addr = Ipv4Addr::new(192, 168, 4, 1);
+
+/// The length of the access point's network prefix.
+pub const AP_PREFIX_LEN: u8 = 24;
+
+/// The page opened by devices joining the access point.
+pub const PORTAL_URL: &str = "http://192.168.4.1/";
+
+static PROVISIONING: AtomicBool = AtomicBool::new(false);
+
+/// Whether the device is running its setup access point rather than joining a network.
+pub fn is_provisioning() -> bool {
+    PROVISIONING.load(Ordering::Relaxed)
+}
+
+/// Records that the setup access point is running, it stays running until a reboot.
+pub fn start_provisioning() {
+    PROVISIONING.store(true, Ordering::Relaxed);
+}
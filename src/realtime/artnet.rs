@@ -0,0 +1,367 @@
+//! Art-Net, which carries DMX universes over UDP. Consoles discover nodes by broadcasting ArtPoll
+//! and send each universe's channels in ArtDmx packets.
+
+#[cfg(feature = "device")]
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    IpAddress, IpEndpoint, Stack,
+};
+#[cfg(feature = "device")]
+use heapless::String;
+#[cfg(feature = "device")]
+use log::{info, warn};
+
+use crate::realtime::DMX_CHANNELS;
+#[cfg(feature = "device")]
+use crate::{
+    leds::{
+        is_realtime, show_realtime, write_realtime, RealtimeSource, StripSettings, MAX_OUTPUTS,
+    },
+    realtime::{is_out_of_order, RealtimeSettings, MAX_UNIVERSES},
+};
+
+/// The UDP port Art-Net is sent to.
+pub const PORT: u16 = 0x1936;
+
+const ID: &[u8] = b"Art-Net\0";
+const OP_POLL: u16 = 0x2000;
+const OP_POLL_REPLY: u16 = 0x2100;
+const OP_DMX: u16 = 0x5000;
+const PROTOCOL_VERSION: u16 = 14;
+
+/// The offset of the channels in an ArtDmx packet.
+const DMX_DATA: usize = 18;
+
+/// The largest 15 bit port address.
+pub const MAX_UNIVERSE: u16 = 0x7FFF;
+
+/// The length of an ArtPollReply packet.
+pub const POLL_REPLY_LEN: usize = 239;
+
+/// The most universes a single ArtPollReply can describe.
+pub const PORTS_PER_REPLY: usize = 4;
+
+const SHORT_NAME_LEN: usize = 18;
+const LONG_NAME_LEN: usize = 64;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ArtNetError {
+    /// The packet ends before its header does.
+    Truncated,
+    /// The packet isn't Art-Net.
+    NotArtNet,
+    /// The packet is for a newer version of the protocol.
+    UnsupportedVersion,
+    /// The packet is an operation that isn't handled.
+    UnsupportedOpCode(u16),
+}
+
+/// The Art-Net packets that are handled.
+pub enum ArtPacket<'a> {
+    /// A console looking for nodes, answered with an ArtPollReply.
+    Poll,
+    Dmx {
+        /// The 15 bit port address, combining the net, sub-net and universe.
+        universe: u16,
+        /// 0 when the source doesn't sequence its packets.
+        sequence: u8,
+        channels: &'a [u8],
+    },
+}
+
+/// Parses an ArtPoll or ArtDmx packet.
+pub fn parse(packet: &[u8]) -> Result<ArtPacket<'_>, ArtNetError> {
+    if packet.len() < 12 {
+        return Err(ArtNetError::Truncated);
+    }
+    if &packet[..8] != ID {
+        return Err(ArtNetError::NotArtNet);
+    }
+
+    let op_code = u16::from_le_bytes([packet[8], packet[9]]);
+    let version = u16::from_be_bytes([packet[10], packet[11]]);
+    if version < PROTOCOL_VERSION {
+        return Err(ArtNetError::UnsupportedVersion);
+    }
+
+    match op_code {
+        OP_POLL => Ok(ArtPacket::Poll),
+        OP_DMX => {
+            if packet.len() < DMX_DATA {
+                return Err(ArtNetError::Truncated);
+            }
+
+            let universe = u16::from_le_bytes([packet[14], packet[15]]) & MAX_UNIVERSE;
+            let len = usize::from(u16::from_be_bytes([packet[16], packet[17]]));
+            let channels = packet
+                .get(DMX_DATA..DMX_DATA + len.min(DMX_CHANNELS))
+                .ok_or(ArtNetError::Truncated)?;
+
+            Ok(ArtPacket::Dmx {
+                universe,
+                sequence: packet[12],
+                channels,
+            })
+        }
+        op_code => Err(ArtNetError::UnsupportedOpCode(op_code)),
+    }
+}
+
+/// Describes this node in answer to an ArtPoll. Nodes with more universes than fit in one reply
+/// send several, each with its own bind index. The universes in a reply must share their net and
+/// sub-net.
+pub struct PollReply<'a> {
+    pub ip: [u8; 4],
+    pub short_name: &'a str,
+    pub long_name: &'a str,
+    /// The port address of the first universe in this reply.
+    pub universe: u16,
+    /// The universes in this reply, at most `PORTS_PER_REPLY`.
+    pub ports: usize,
+    /// 1 for the first reply, incrementing for each further reply.
+    pub bind_index: u8,
+}
+
+fn copy_str(target: &mut [u8], value: &str) {
+    // Names are null terminated.
+    let len = value.len().min(target.len() - 1);
+    target[..len].copy_from_slice(&value.as_bytes()[..len]);
+}
+
+impl PollReply<'_> {
+    pub fn encode(&self, buf: &mut [u8; POLL_REPLY_LEN]) {
+        buf.fill(0);
+
+        buf[..8].copy_from_slice(ID);
+        buf[8..10].copy_from_slice(&OP_POLL_REPLY.to_le_bytes());
+        buf[10..14].copy_from_slice(&self.ip);
+        buf[14..16].copy_from_slice(&PORT.to_le_bytes());
+        // The net and sub-net are shared by all the ports in a reply.
+        buf[18] = (self.universe >> 8) as u8 & 0x7F;
+        buf[19] = (self.universe >> 4) as u8 & 0x0F;
+        // Indicators normal, addresses set from the network.
+        buf[23] = 0b1110_0000;
+        copy_str(&mut buf[26..26 + SHORT_NAME_LEN], self.short_name);
+        copy_str(&mut buf[44..44 + LONG_NAME_LEN], self.long_name);
+        copy_str(&mut buf[108..172], "#0001 [0000] Power On Tests successful");
+
+        let ports = self.ports.min(PORTS_PER_REPLY);
+        buf[173] = ports as u8;
+        for port in 0..ports {
+            // Outputs DMX512.
+            buf[174 + port] = 0x80;
+            // Data is being transmitted.
+            buf[182 + port] = 0x80;
+            buf[190 + port] = (self.universe.wrapping_add(port as u16) & 0x0F) as u8;
+        }
+
+        buf[207..211].copy_from_slice(&self.ip);
+        buf[211] = self.bind_index;
+        // Supports 15 bit port addresses and DHCP, which is in use.
+        buf[212] = 0b0000_1110;
+    }
+}
+
+/// Answers an ArtPoll from `console` for every universe covering the strips, splitting them across
+/// replies.
+#[cfg(feature = "device")]
+async fn reply_to_poll(
+    network: Stack<'static>,
+    socket: &mut UdpSocket<'_>,
+    console: IpAddress,
+    board_id: &str,
+    universe: u16,
+    universes: usize,
+) {
+    let Some(config) = network.config_v4() else {
+        return;
+    };
+
+    let mut long_name = String::<LONG_NAME_LEN>::new();
+    let _ = long_name.push_str("blinky ");
+    let _ = long_name.push_str(board_id);
+
+    let mut buf = [0; POLL_REPLY_LEN];
+    let console = IpEndpoint::new(console, PORT);
+    let mut first = 0;
+    let mut bind_index = 1;
+    while first < universes {
+        let universe = universe.wrapping_add(first as u16);
+        // A reply can't cross into the next sub-net.
+        let ports = (universes - first)
+            .min(PORTS_PER_REPLY)
+            .min(16 - usize::from(universe & 0x0F));
+
+        PollReply {
+            ip: config.address.address().octets(),
+            short_name: "blinky",
+            long_name: &long_name,
+            universe,
+            ports,
+            bind_index,
+        }
+        .encode(&mut buf);
+
+        if let Err(e) = socket.send_to(&buf, console).await {
+            warn!("Failed to send ArtPollReply: {e:?}");
+        }
+
+        first += ports;
+        bind_index += 1;
+    }
+}
+
+/// Shows Art-Net data sent to the configured universes and answers discovery polls.
+#[cfg(feature = "device")]
+#[embassy_executor::task]
+pub async fn artnet_task(network: Stack<'static>, board_id: &'static str) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 4 * (DMX_DATA + DMX_CHANNELS)];
+    // Crossing a sub-net can take an extra reply.
+    let mut tx_meta = [PacketMetadata::EMPTY; MAX_UNIVERSES.div_ceil(PORTS_PER_REPLY) + 1];
+    let mut tx_buffer = [0; (MAX_UNIVERSES.div_ceil(PORTS_PER_REPLY) + 1) * POLL_REPLY_LEN];
+    let mut socket = UdpSocket::new(
+        network,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(PORT) {
+        warn!("Failed to listen for Art-Net: {e:?}");
+        return;
+    }
+
+    let mut packet = [0; DMX_DATA + DMX_CHANNELS];
+    let mut sequences: [Option<u8>; MAX_UNIVERSES] = [None; MAX_UNIVERSES];
+
+    loop {
+        let Ok((len, meta)) = socket.recv_from(&mut packet).await else {
+            // Packets too large for the buffer aren't ones that are handled.
+            continue;
+        };
+
+        let settings = RealtimeSettings::get();
+        let Some(mapping) = settings.artnet else {
+            continue;
+        };
+
+        match parse(&packet[..len]) {
+            Ok(ArtPacket::Poll) => {
+                let pixels = (0..MAX_OUTPUTS)
+                    .map(|output| StripSettings::get(output).pixels)
+                    .sum();
+                let universes = mapping.universes(pixels).min(MAX_UNIVERSES);
+                reply_to_poll(
+                    network,
+                    &mut socket,
+                    meta.endpoint.addr,
+                    board_id,
+                    mapping.universe,
+                    universes,
+                )
+                .await;
+            }
+            Ok(ArtPacket::Dmx {
+                universe,
+                sequence,
+                channels,
+            }) => {
+                let Some((first, channels)) = mapping.locate(universe, channels) else {
+                    continue;
+                };
+
+                if !is_realtime() {
+                    // A new stream starts its sequences afresh.
+                    sequences = [None; MAX_UNIVERSES];
+                }
+                let Some(last) = sequences.get_mut(usize::from(universe - mapping.universe)) else {
+                    continue;
+                };
+                // Sources that don't sequence their packets always send 0.
+                if sequence != 0 {
+                    if last.is_some_and(|last| is_out_of_order(last, sequence)) {
+                        continue;
+                    }
+                    *last = Some(sequence);
+                }
+
+                if !is_realtime() {
+                    info!("Art-Net stream started");
+                }
+                write_realtime(first, channels);
+                show_realtime(RealtimeSource::ArtNet, settings.timeout);
+            }
+            Err(_) => {}
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn header(op_code: u16) -> Vec<u8> {
+        let mut packet = ID.to_vec();
+        packet.extend_from_slice(&op_code.to_le_bytes());
+        packet.extend_from_slice(&PROTOCOL_VERSION.to_be_bytes());
+        packet
+    }
+
+    fn dmx(universe: u16, sequence: u8, channels: &[u8]) -> Vec<u8> {
+        let mut packet = header(OP_DMX);
+        packet.extend_from_slice(&[sequence, 0]);
+        packet.extend_from_slice(&universe.to_le_bytes());
+        packet.extend_from_slice(&(channels.len() as u16).to_be_bytes());
+        packet.extend_from_slice(channels);
+        packet
+    }
+
+    #[test]
+    fn parses_poll_and_dmx() {
+        assert!(matches!(parse(&header(OP_POLL)), Ok(ArtPacket::Poll)));
+
+        let packet = dmx(0x0123, 9, &[1, 2, 3]);
+        let Ok(ArtPacket::Dmx {
+            universe,
+            sequence,
+            channels,
+        }) = parse(&packet)
+        else {
+            panic!("not an ArtDmx packet");
+        };
+        assert_eq!(universe, 0x0123);
+        assert_eq!(sequence, 9);
+        assert_eq!(channels, [1, 2, 3]);
+    }
+
+    #[test]
+    fn limits_dmx_to_a_universe() {
+        let packet = dmx(0, 0, &[7; DMX_CHANNELS + 2]);
+
+        assert!(matches!(
+            parse(&packet),
+            Ok(ArtPacket::Dmx { channels, .. }) if channels.len() == DMX_CHANNELS
+        ));
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        let packet = dmx(0, 0, &[1, 2, 3]);
+        assert_eq!(
+            parse(&packet[..packet.len() - 1]).err(),
+            Some(ArtNetError::Truncated)
+        );
+        assert_eq!(parse(b"Art-Net").err(), Some(ArtNetError::Truncated));
+        assert_eq!(
+            parse(b"Blah-Net\0\x50\0\x0e").err(),
+            Some(ArtNetError::NotArtNet)
+        );
+        assert_eq!(
+            parse(&header(0x2100)).err(),
+            Some(ArtNetError::UnsupportedOpCode(0x2100))
+        );
+    }
+}
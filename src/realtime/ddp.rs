@@ -0,0 +1,255 @@
+//! DDP (Distributed Display Protocol), which carries pixel data at byte offsets into the display.
+//! Senders split a frame across several packets and set the push flag on the last so the frame is
+//! shown all at once.
+
+#[cfg(feature = "device")]
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Stack,
+};
+#[cfg(feature = "device")]
+use embassy_time::Instant;
+#[cfg(feature = "device")]
+use log::{info, warn};
+
+#[cfg(feature = "device")]
+use crate::{
+    leds::{show_realtime, write_realtime, RealtimeSource},
+    realtime::RealtimeSettings,
+};
+
+/// The UDP port DDP is sent to.
+pub const PORT: u16 = 4048;
+
+const VERSION_MASK: u8 = 0xC0;
+const VERSION_1: u8 = 0x40;
+const FLAG_TIMECODE: u8 = 0x10;
+const FLAG_QUERY: u8 = 0x02;
+const FLAG_PUSH: u8 = 0x01;
+
+const HEADER_LEN: usize = 10;
+const TIMECODE_LEN: usize = 4;
+
+/// The destination of pixel data for the display.
+const ID_DISPLAY: u8 = 1;
+/// Every destination.
+const ID_ALL: u8 = 255;
+
+/// The largest packet senders are expected to use.
+#[cfg(feature = "device")]
+const MAX_PACKET_LEN: usize = HEADER_LEN + TIMECODE_LEN + 1440;
+
+/// The bytes in each pixel of RGB data.
+const PIXEL_LEN: u32 = 3;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DdpError {
+    /// The packet ends before its header or data do.
+    Truncated,
+    UnsupportedVersion,
+}
+
+pub struct DdpPacket<'a> {
+    /// 1 to 15, 0 when the sender doesn't sequence its packets.
+    pub sequence: u8,
+    /// The last packet of a frame, which should now be shown.
+    pub push: bool,
+    /// The sender is asking for information rather than sending data.
+    pub query: bool,
+    pub data_type: u8,
+    pub destination: u8,
+    /// The byte offset of the data in the display.
+    pub offset: u32,
+    pub data: &'a [u8],
+}
+
+impl DdpPacket<'_> {
+    /// Whether the packet holds pixel data for the display as 8 bit RGB.
+    pub fn is_rgb_pixels(&self) -> bool {
+        // 0x01 is the type used before types were fully defined, 0x0B is RGB at 8 bits per channel.
+        !self.query
+            && matches!(self.destination, ID_DISPLAY | ID_ALL)
+            && matches!(self.data_type, 0x00 | 0x01 | 0x0B)
+    }
+
+    /// The pixel the data starts at, `None` if it starts part way through one.
+    pub fn first_pixel(&self) -> Option<usize> {
+        let pixel = self.offset / PIXEL_LEN;
+        (pixel * PIXEL_LEN == self.offset).then_some(pixel as usize)
+    }
+}
+
+/// Parses a DDP packet.
+pub fn parse(packet: &[u8]) -> Result<DdpPacket<'_>, DdpError> {
+    if packet.len() < HEADER_LEN {
+        return Err(DdpError::Truncated);
+    }
+
+    let flags = packet[0];
+    if flags & VERSION_MASK != VERSION_1 {
+        return Err(DdpError::UnsupportedVersion);
+    }
+
+    let start = if flags & FLAG_TIMECODE != 0 {
+        HEADER_LEN + TIMECODE_LEN
+    } else {
+        HEADER_LEN
+    };
+    let len = usize::from(u16::from_be_bytes([packet[8], packet[9]]));
+
+    Ok(DdpPacket {
+        sequence: packet[1] & 0x0F,
+        push: flags & FLAG_PUSH != 0,
+        query: flags & FLAG_QUERY != 0,
+        data_type: packet[2],
+        destination: packet[3],
+        offset: u32::from_be_bytes([packet[4], packet[5], packet[6], packet[7]]),
+        data: packet.get(start..start + len).ok_or(DdpError::Truncated)?,
+    })
+}
+
+/// Shows DDP pixel data while it is enabled. Once a sender has used the push flag the data is only
+/// shown when a packet is pushed, otherwise each packet is shown as it arrives.
+#[cfg(feature = "device")]
+#[embassy_executor::task]
+pub async fn ddp_task(network: Stack<'static>) {
+    // Room for a whole frame across every output.
+    let mut rx_meta = [PacketMetadata::EMPTY; 3];
+    let mut rx_buffer = [0; 3 * MAX_PACKET_LEN];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 0];
+    let mut socket = UdpSocket::new(
+        network,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(PORT) {
+        warn!("Failed to listen for DDP: {e:?}");
+        return;
+    }
+
+    let mut packet = [0; MAX_PACKET_LEN];
+    let mut uses_push = false;
+    let mut last_packet: Option<Instant> = None;
+
+    loop {
+        let Ok((len, _)) = socket.recv_from(&mut packet).await else {
+            continue;
+        };
+        let settings = RealtimeSettings::get();
+        if !settings.ddp {
+            continue;
+        }
+        let Ok(data) = parse(&packet[..len]) else {
+            continue;
+        };
+        if !data.is_rgb_pixels() {
+            continue;
+        }
+        let Some(first) = data.first_pixel() else {
+            continue;
+        };
+
+        if !last_packet.is_some_and(|last| last.elapsed() <= settings.timeout) {
+            info!("DDP stream started");
+            uses_push = false;
+        }
+        last_packet = Some(Instant::now());
+        uses_push |= data.push;
+
+        write_realtime(first, data.data);
+        if data.push || !uses_push {
+            show_realtime(RealtimeSource::Ddp, settings.timeout);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    fn packet(flags: u8, offset: u32, data: &[u8]) -> Vec<u8> {
+        let mut packet = std::vec![VERSION_1 | flags, 0x05, 0x0B, ID_DISPLAY];
+        packet.extend_from_slice(&offset.to_be_bytes());
+        packet.extend_from_slice(&(data.len() as u16).to_be_bytes());
+        packet.extend_from_slice(data);
+        packet
+    }
+
+    #[test]
+    fn parses_pixel_data() {
+        let packet = packet(FLAG_PUSH, 6, &[1, 2, 3]);
+        let data = parse(&packet).unwrap();
+
+        assert_eq!(data.sequence, 5);
+        assert!(data.push);
+        assert!(!data.query);
+        assert_eq!(data.offset, 6);
+        assert_eq!(data.data, [1, 2, 3]);
+        assert!(data.is_rgb_pixels());
+        assert_eq!(data.first_pixel(), Some(2));
+    }
+
+    #[test]
+    fn skips_the_timecode() {
+        let mut packet = packet(FLAG_TIMECODE, 0, &[]);
+        packet.extend_from_slice(&[0xAA; TIMECODE_LEN]);
+        packet[9] = 3;
+        packet.extend_from_slice(&[1, 2, 3]);
+
+        assert_eq!(parse(&packet).unwrap().data, [1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let packet = packet(0, 0, &[1, 2, 3]);
+
+        assert_eq!(
+            parse(&packet[..HEADER_LEN - 1]).err(),
+            Some(DdpError::Truncated)
+        );
+        assert_eq!(
+            parse(&packet[..packet.len() - 1]).err(),
+            Some(DdpError::Truncated)
+        );
+        // Only the length in the header is read, anything after it is ignored.
+        assert_eq!(
+            parse(&[packet.as_slice(), &[9]].concat()).unwrap().data,
+            [1, 2, 3]
+        );
+    }
+
+    #[test]
+    fn rejects_other_versions() {
+        let mut packet = packet(0, 0, &[1, 2, 3]);
+        packet[0] = 0x80;
+
+        assert_eq!(parse(&packet).err(), Some(DdpError::UnsupportedVersion));
+    }
+
+    #[test]
+    fn recognises_pixel_data() {
+        let mut query = packet(FLAG_QUERY, 0, &[]);
+        assert!(!parse(&query).unwrap().is_rgb_pixels());
+
+        query[0] = VERSION_1;
+        query[3] = 2;
+        assert!(!parse(&query).unwrap().is_rgb_pixels());
+
+        query[3] = ID_ALL;
+        query[2] = 0x1B;
+        assert!(!parse(&query).unwrap().is_rgb_pixels());
+    }
+
+    #[test]
+    fn finds_the_first_whole_pixel() {
+        let unaligned = packet(0, 4, &[1, 2, 3]);
+
+        assert_eq!(parse(&unaligned).unwrap().first_pixel(), None);
+        assert_eq!(parse(&packet(0, 0, &[])).unwrap().first_pixel(), Some(0));
+    }
+}
@@ -0,0 +1,339 @@
+//! E1.31 (streaming ACN), which carries DMX universes over UDP. Only data packets are handled,
+//! synchronisation and discovery packets are ignored.
+
+#[cfg(feature = "device")]
+use embassy_futures::select::{select, Either};
+#[cfg(feature = "device")]
+use embassy_net::{
+    udp::{PacketMetadata, UdpSocket},
+    Ipv4Address, Stack,
+};
+#[cfg(feature = "device")]
+use embassy_time::Instant;
+#[cfg(feature = "device")]
+use log::{info, warn};
+
+#[cfg(feature = "device")]
+use crate::{
+    leds::{
+        is_realtime, realtime_source, show_realtime, stop_realtime, write_realtime, RealtimeSource,
+        StripSettings, MAX_OUTPUTS,
+    },
+    realtime::{
+        is_out_of_order, DmxMapping, RealtimeSettings, DMX_CHANNELS, MAX_UNIVERSES,
+        REALTIME_SETTINGS_CHANGED,
+    },
+};
+
+/// The UDP port E1.31 is sent to.
+pub const PORT: u16 = 5568;
+
+const ACN_IDENTIFIER: &[u8] = b"ASC-E1.17\0\0\0";
+const VECTOR_ROOT_E131_DATA: u32 = 0x0000_0004;
+const VECTOR_E131_DATA_PACKET: u32 = 0x0000_0002;
+const VECTOR_DMP_SET_PROPERTY: u8 = 0x02;
+
+const OPTION_PREVIEW_DATA: u8 = 0x80;
+const OPTION_STREAM_TERMINATED: u8 = 0x40;
+
+/// The offset of the DMX start code, the channels follow it.
+const PROPERTY_VALUES: usize = 125;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum E131Error {
+    /// The packet ends before its layers do.
+    Truncated,
+    /// The packet isn't ACN.
+    NotE131,
+    /// The packet is E1.31 but not DMX data.
+    UnsupportedVector,
+}
+
+/// A DMX data packet.
+pub struct DataPacket<'a> {
+    pub universe: u16,
+    /// Receivers show the data from the highest priority source, 0 to 200.
+    pub priority: u8,
+    pub sequence: u8,
+    /// The data is meant for visualisers and shouldn't be shown.
+    pub preview: bool,
+    /// The source has stopped sending to the universe.
+    pub terminated: bool,
+    /// 0 for dimmer data.
+    pub start_code: u8,
+    /// The channel values, without the start code.
+    pub channels: &'a [u8],
+}
+
+fn u16_at(packet: &[u8], offset: usize) -> u16 {
+    u16::from_be_bytes([packet[offset], packet[offset + 1]])
+}
+
+fn u32_at(packet: &[u8], offset: usize) -> u32 {
+    u32::from_be_bytes([
+        packet[offset],
+        packet[offset + 1],
+        packet[offset + 2],
+        packet[offset + 3],
+    ])
+}
+
+/// Parses a data packet.
+pub fn parse(packet: &[u8]) -> Result<DataPacket<'_>, E131Error> {
+    if packet.len() < 16 {
+        return Err(E131Error::Truncated);
+    }
+    if u16_at(packet, 0) != 0x0010 || &packet[4..16] != ACN_IDENTIFIER {
+        return Err(E131Error::NotE131);
+    }
+    if packet.len() < 22 {
+        return Err(E131Error::Truncated);
+    }
+    if u32_at(packet, 18) != VECTOR_ROOT_E131_DATA {
+        return Err(E131Error::UnsupportedVector);
+    }
+    if packet.len() <= PROPERTY_VALUES {
+        return Err(E131Error::Truncated);
+    }
+    if u32_at(packet, 40) != VECTOR_E131_DATA_PACKET || packet[117] != VECTOR_DMP_SET_PROPERTY {
+        return Err(E131Error::UnsupportedVector);
+    }
+
+    // The count includes the start code.
+    let count = usize::from(u16_at(packet, 123));
+    if count == 0 {
+        return Err(E131Error::Truncated);
+    }
+    let values = packet
+        .get(PROPERTY_VALUES..PROPERTY_VALUES + count)
+        .ok_or(E131Error::Truncated)?;
+    let options = packet[112];
+
+    Ok(DataPacket {
+        universe: u16_at(packet, 113),
+        priority: packet[108],
+        sequence: packet[111],
+        preview: options & OPTION_PREVIEW_DATA != 0,
+        terminated: options & OPTION_STREAM_TERMINATED != 0,
+        start_code: values[0],
+        channels: &values[1..],
+    })
+}
+
+/// The multicast group a universe is sent to.
+#[cfg(feature = "device")]
+fn multicast_group(universe: u16) -> Ipv4Address {
+    let [high, low] = universe.to_be_bytes();
+    Ipv4Address::new(239, 255, high, low)
+}
+
+/// Joins the multicast groups of the universes covering the strips, leaving any no longer needed.
+#[cfg(feature = "device")]
+fn join_universes(network: Stack<'static>, joined: &mut (u16, usize), mapping: Option<DmxMapping>) {
+    let wanted = match mapping {
+        Some(mapping) => {
+            let pixels = (0..MAX_OUTPUTS)
+                .map(|output| StripSettings::get(output).pixels)
+                .sum();
+            (
+                mapping.universe,
+                mapping.universes(pixels).min(MAX_UNIVERSES),
+            )
+        }
+        None => (0, 0),
+    };
+
+    if wanted == *joined {
+        return;
+    }
+
+    let (first, count) = *joined;
+    for universe in (first..).take(count) {
+        let _ = network.leave_multicast_group(multicast_group(universe));
+    }
+
+    let (first, count) = wanted;
+    for universe in (first..).take(count) {
+        if network
+            .join_multicast_group(multicast_group(universe))
+            .is_err()
+        {
+            warn!("Failed to join the multicast group for universe {universe}");
+        }
+    }
+
+    *joined = wanted;
+}
+
+/// Shows E1.31 data sent to the configured universes. Only the highest priority source is shown,
+/// a lower priority source takes over once the higher one times out.
+#[cfg(feature = "device")]
+#[embassy_executor::task]
+pub async fn e131_task(network: Stack<'static>) {
+    let mut rx_meta = [PacketMetadata::EMPTY; 4];
+    let mut rx_buffer = [0; 4 * (PROPERTY_VALUES + 1 + DMX_CHANNELS)];
+    let mut tx_meta = [PacketMetadata::EMPTY; 1];
+    let mut tx_buffer = [0; 0];
+    let mut socket = UdpSocket::new(
+        network,
+        &mut rx_meta,
+        &mut rx_buffer,
+        &mut tx_meta,
+        &mut tx_buffer,
+    );
+    if let Err(e) = socket.bind(PORT) {
+        warn!("Failed to listen for E1.31: {e:?}");
+        return;
+    }
+
+    let mut packet = [0; PROPERTY_VALUES + 1 + DMX_CHANNELS];
+    let mut joined = (0, 0);
+    let mut sequences: [Option<u8>; MAX_UNIVERSES] = [None; MAX_UNIVERSES];
+    let mut priority = 0;
+    let mut priority_until = Instant::now();
+
+    loop {
+        let settings = RealtimeSettings::get();
+        join_universes(network, &mut joined, settings.e131);
+
+        let len = match select(
+            socket.recv_from(&mut packet),
+            REALTIME_SETTINGS_CHANGED.wait(),
+        )
+        .await
+        {
+            Either::First(Ok((len, _))) => len,
+            // Packets too large for the buffer aren't E1.31.
+            Either::First(Err(_)) => continue,
+            Either::Second(_) => continue,
+        };
+
+        let Some(mapping) = settings.e131 else {
+            continue;
+        };
+        let Ok(data) = parse(&packet[..len]) else {
+            continue;
+        };
+        if data.preview || data.start_code != 0 {
+            continue;
+        }
+        let Some((first, channels)) = mapping.locate(data.universe, data.channels) else {
+            continue;
+        };
+
+        let now = Instant::now();
+        if data.priority < priority && now < priority_until {
+            continue;
+        }
+        if !is_realtime() {
+            // A new stream starts its sequences afresh.
+            sequences = [None; MAX_UNIVERSES];
+        }
+        let Some(last) = sequences.get_mut(usize::from(data.universe - mapping.universe)) else {
+            continue;
+        };
+        if last.is_some_and(|last| is_out_of_order(last, data.sequence)) {
+            continue;
+        }
+        *last = Some(data.sequence);
+
+        if data.terminated {
+            info!("E1.31 stream ended");
+            sequences = [None; MAX_UNIVERSES];
+            priority = 0;
+            // Leave any other protocol's data showing.
+            if realtime_source() == Some(RealtimeSource::E131) {
+                stop_realtime();
+            }
+            continue;
+        }
+
+        priority = data.priority;
+        priority_until = now + settings.timeout;
+
+        if !is_realtime() {
+            info!("E1.31 stream started");
+        }
+        write_realtime(first, channels);
+        show_realtime(RealtimeSource::E131, settings.timeout);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::vec::Vec;
+
+    use super::*;
+
+    /// A data packet for `universe` holding `channels` after a 0 start code.
+    fn packet(universe: u16, sequence: u8, channels: &[u8]) -> Vec<u8> {
+        let mut packet = std::vec![0; PROPERTY_VALUES + 1];
+        packet[..2].copy_from_slice(&0x0010_u16.to_be_bytes());
+        packet[4..16].copy_from_slice(ACN_IDENTIFIER);
+        packet[18..22].copy_from_slice(&VECTOR_ROOT_E131_DATA.to_be_bytes());
+        packet[40..44].copy_from_slice(&VECTOR_E131_DATA_PACKET.to_be_bytes());
+        packet[108] = 100;
+        packet[111] = sequence;
+        packet[113..115].copy_from_slice(&universe.to_be_bytes());
+        packet[117] = VECTOR_DMP_SET_PROPERTY;
+        packet[123..125].copy_from_slice(&(channels.len() as u16 + 1).to_be_bytes());
+        packet.extend_from_slice(channels);
+        packet
+    }
+
+    #[test]
+    fn parses_data() {
+        let mut packet = packet(7, 42, &[1, 2, 3]);
+        packet[112] = OPTION_PREVIEW_DATA;
+        let data = parse(&packet).unwrap();
+
+        assert_eq!(data.universe, 7);
+        assert_eq!(data.priority, 100);
+        assert_eq!(data.sequence, 42);
+        assert!(data.preview);
+        assert!(!data.terminated);
+        assert_eq!(data.start_code, 0);
+        assert_eq!(data.channels, [1, 2, 3]);
+    }
+
+    #[test]
+    fn ignores_trailing_bytes() {
+        let mut packet = packet(1, 0, &[1, 2, 3]);
+        packet.extend_from_slice(&[9, 9]);
+
+        assert_eq!(parse(&packet).unwrap().channels, [1, 2, 3]);
+    }
+
+    #[test]
+    fn rejects_truncated_packets() {
+        let packet = packet(1, 0, &[1, 2, 3]);
+
+        for len in [0, 15, 21, PROPERTY_VALUES, packet.len() - 1] {
+            assert!(matches!(parse(&packet[..len]), Err(E131Error::Truncated)));
+        }
+    }
+
+    #[test]
+    fn rejects_other_packets() {
+        let mut other = packet(1, 0, &[1, 2, 3]);
+        other[4] = b'X';
+        assert!(matches!(parse(&other), Err(E131Error::NotE131)));
+
+        // Synchronisation packets have their own root vector.
+        let mut sync = packet(1, 0, &[1, 2, 3]);
+        sync[18..22].copy_from_slice(&8_u32.to_be_bytes());
+        assert!(matches!(parse(&sync), Err(E131Error::UnsupportedVector)));
+
+        let mut frame = packet(1, 0, &[1, 2, 3]);
+        frame[40..44].copy_from_slice(&1_u32.to_be_bytes());
+        assert!(matches!(parse(&frame), Err(E131Error::UnsupportedVector)));
+    }
+
+    #[test]
+    fn reads_stream_termination() {
+        let mut packet = packet(1, 0, &[]);
+        packet[112] = OPTION_STREAM_TERMINATED;
+
+        assert!(parse(&packet).unwrap().terminated);
+    }
+}
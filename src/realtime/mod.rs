@@ -0,0 +1,175 @@
+//! Realtime protocols that let lighting software on the network stream pixel data to the strips.
+//! The parsers don't depend on the hardware, the listeners run on the board's network stack.
+
+use core::cell::Cell;
+
+use embassy_sync::{
+    blocking_mutex::{raw::CriticalSectionRawMutex, Mutex},
+    signal::Signal,
+};
+use embassy_time::Duration;
+
+use crate::leds::{MAX_OUTPUTS, MAX_PIXELS};
+
+pub mod artnet;
+pub mod ddp;
+pub mod e131;
+
+/// The channels in a DMX universe.
+pub const DMX_CHANNELS: usize = 512;
+
+/// The pixels in each universe after the first, which start at their first channel.
+const PIXELS_PER_UNIVERSE: usize = DMX_CHANNELS / 3;
+
+/// The most universes needed to cover every pixel of every output.
+pub const MAX_UNIVERSES: usize = (MAX_OUTPUTS * MAX_PIXELS).div_ceil(PIXELS_PER_UNIVERSE) + 1;
+
+/// Where the pixels are in a run of consecutive DMX universes. A pixel never straddles two
+/// universes, so the channels left over at the end of each are unused.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct DmxMapping {
+    /// The universe holding the first pixel.
+    pub universe: u16,
+    /// The channel of the first pixel in its universe, from 1.
+    pub start_channel: u16,
+}
+
+impl DmxMapping {
+    fn skipped_channels(&self) -> usize {
+        usize::from(self.start_channel.clamp(1, DMX_CHANNELS as u16) - 1)
+    }
+
+    /// The first pixel held by a universe along with its channels from that pixel on, `None` if
+    /// the universe comes before the mapping.
+    pub fn locate<'a>(&self, universe: u16, channels: &'a [u8]) -> Option<(usize, &'a [u8])> {
+        let index = usize::from(universe.checked_sub(self.universe)?);
+        let skipped = self.skipped_channels();
+
+        if index == 0 {
+            Some((0, channels.get(skipped..).unwrap_or_default()))
+        } else {
+            let first = (DMX_CHANNELS - skipped) / 3 + (index - 1) * PIXELS_PER_UNIVERSE;
+            Some((first, channels))
+        }
+    }
+
+    /// The number of universes needed to cover `pixels` pixels.
+    pub fn universes(&self, pixels: usize) -> usize {
+        let in_first = (DMX_CHANNELS - self.skipped_channels()) / 3;
+        1 + pixels
+            .saturating_sub(in_first)
+            .div_ceil(PIXELS_PER_UNIVERSE)
+    }
+}
+
+/// Whether a sequence number shows a packet arrived after a later one and should be dropped.
+pub fn is_out_of_order(last: u8, sequence: u8) -> bool {
+    let difference = sequence.wrapping_sub(last) as i8;
+    difference <= 0 && difference > -20
+}
+
+static REALTIME_SETTINGS: Mutex<CriticalSectionRawMutex, Cell<RealtimeSettings>> =
+    Mutex::new(Cell::new(RealtimeSettings {
+        e131: None,
+        artnet: None,
+        ddp: false,
+        timeout: Duration::from_millis(2500),
+    }));
+
+/// Signalled when the realtime settings or the strip layout change.
+pub static REALTIME_SETTINGS_CHANGED: Signal<CriticalSectionRawMutex, ()> = Signal::new();
+
+/// How the listeners map streams onto the pixels, read as each packet arrives.
+#[derive(Clone, Copy, PartialEq, Eq)]
+pub struct RealtimeSettings {
+    /// `None` ignores E1.31.
+    pub e131: Option<DmxMapping>,
+    /// `None` ignores Art-Net.
+    pub artnet: Option<DmxMapping>,
+    /// Whether DDP is shown.
+    pub ddp: bool,
+    /// How long a stream is shown after its last packet.
+    pub timeout: Duration,
+}
+
+impl RealtimeSettings {
+    pub fn get() -> Self {
+        REALTIME_SETTINGS.lock(|settings| settings.get())
+    }
+
+    pub fn set(settings: RealtimeSettings) {
+        REALTIME_SETTINGS.lock(|cell| cell.set(settings));
+        REALTIME_SETTINGS_CHANGED.signal(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn orders_sequences_across_wraparound() {
+        assert!(!is_out_of_order(10, 11));
+        assert!(is_out_of_order(10, 10));
+        assert!(is_out_of_order(10, 9));
+        assert!(!is_out_of_order(255, 0));
+        assert!(!is_out_of_order(250, 3));
+        assert!(is_out_of_order(3, 250));
+        // Far enough back to be a restarted source rather than a late packet.
+        assert!(!is_out_of_order(100, 80));
+    }
+
+    #[test]
+    fn locates_pixels_across_universes() {
+        let mapping = DmxMapping {
+            universe: 3,
+            start_channel: 1,
+        };
+        let channels = [0; DMX_CHANNELS];
+
+        assert!(mapping.locate(2, &channels).is_none());
+        assert_eq!(
+            mapping.locate(3, &channels).map(|(first, _)| first),
+            Some(0)
+        );
+        let (first, located) = mapping.locate(4, &channels).unwrap();
+        assert_eq!(first, PIXELS_PER_UNIVERSE);
+        assert_eq!(located.len(), DMX_CHANNELS);
+    }
+
+    #[test]
+    fn skips_channels_in_the_first_universe() {
+        let mapping = DmxMapping {
+            universe: 1,
+            start_channel: 4,
+        };
+        let channels: [u8; DMX_CHANNELS] = core::array::from_fn(|i| i as u8);
+
+        let (first, located) = mapping.locate(1, &channels).unwrap();
+        assert_eq!(first, 0);
+        assert_eq!(located[..3], [3, 4, 5]);
+        // 509 channels leave room for 169 pixels.
+        assert_eq!(mapping.locate(2, &channels).unwrap().0, 169);
+    }
+
+    #[test]
+    fn counts_universes_at_the_boundary() {
+        let mapping = DmxMapping {
+            universe: 1,
+            start_channel: 1,
+        };
+
+        assert_eq!(mapping.universes(0), 1);
+        assert_eq!(mapping.universes(PIXELS_PER_UNIVERSE), 1);
+        assert_eq!(mapping.universes(PIXELS_PER_UNIVERSE + 1), 2);
+        assert_eq!(mapping.universes(2 * PIXELS_PER_UNIVERSE), 2);
+        assert!(mapping.universes(MAX_OUTPUTS * MAX_PIXELS) <= MAX_UNIVERSES);
+
+        let late = DmxMapping {
+            universe: 1,
+            start_channel: DMX_CHANNELS as u16,
+        };
+        assert_eq!(late.universes(1), 2);
+        assert!(late.universes(MAX_OUTPUTS * MAX_PIXELS) <= MAX_UNIVERSES);
+    }
+}